@@ -1,56 +1,470 @@
 use crate::error::Error;
-use flate2::read::GzDecoder;
+use crate::extraction_policy::{ExtractionPolicy, Tracker};
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use glob::{MatchOptions, Pattern};
 use std::fs::{self, File};
-use std::path::Path;
-use tempfile::tempdir_in;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tempfile::{tempdir_in, NamedTempFile};
 
-/// Supported archive types.
-pub(crate) enum ArchiveFormat {
+/// Supported archive formats.
+///
+/// The format of an archive is detected from its contents, but it can also be set explicitly
+/// with [`Options::archive_format()`](crate::Options::archive_format).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ArchiveFormat {
+    /// An uncompressed tar archive (`.tar`).
+    Tar,
+    /// A gzip-compressed tar archive (`.tar.gz` or `.tgz`).
     TarGz,
+    /// A bzip2-compressed tar archive (`.tar.bz2` or `.tbz2`).
+    TarBz2,
+    /// An xz-compressed tar archive (`.tar.xz` or `.txz`).
+    TarXz,
+    /// A zstd-compressed tar archive (`.tar.zst` or `.tzst`).
+    TarZst,
+    /// A zip archive (`.zip`).
     Zip,
+    /// A single gzip-compressed file (`.gz`).
+    Gz,
+    /// A single bzip2-compressed file (`.bz2`).
+    Bz2,
+    /// A single xz-compressed file (`.xz`).
+    Xz,
+    /// A single zstd-compressed file (`.zst`).
+    Zst,
 }
 
+/// File extensions of each format.
+///
+/// Compound extensions like ".tar.gz" have to come before ".gz".
+const EXTENSIONS: &[(&str, ArchiveFormat)] = &[
+    (".tar", ArchiveFormat::Tar),
+    (".tar.gz", ArchiveFormat::TarGz),
+    (".tgz", ArchiveFormat::TarGz),
+    (".tar.bz2", ArchiveFormat::TarBz2),
+    (".tbz2", ArchiveFormat::TarBz2),
+    (".tar.xz", ArchiveFormat::TarXz),
+    (".txz", ArchiveFormat::TarXz),
+    (".tar.zst", ArchiveFormat::TarZst),
+    (".tzst", ArchiveFormat::TarZst),
+    (".zip", ArchiveFormat::Zip),
+    (".gz", ArchiveFormat::Gz),
+    (".bz2", ArchiveFormat::Bz2),
+    (".xz", ArchiveFormat::Xz),
+    (".zst", ArchiveFormat::Zst),
+];
+
 impl ArchiveFormat {
     /// Parse archive type from resource extension.
     pub(crate) fn parse_from_extension(resource: &str) -> Result<Self, Error> {
-        if resource.ends_with(".tar.gz") {
-            Ok(Self::TarGz)
-        } else if resource.ends_with(".zip") {
-            Ok(Self::Zip)
+        let resource = resource.to_lowercase();
+        EXTENSIONS
+            .iter()
+            .find(|(extension, _)| resource.ends_with(extension))
+            .map(|(_, format)| *format)
+            .ok_or_else(|| Error::invalid_archive("unsupported archive format"))
+    }
+
+    /// Parse archive type from a MIME type, if it implies one.
+    fn parse_from_content_type(content_type: &str) -> Option<Self> {
+        let mime_type = content_type
+            .split(';')
+            .next()
+            .unwrap()
+            .trim()
+            .to_lowercase();
+        match mime_type.as_str() {
+            "application/x-tar" => Some(Self::Tar),
+            "application/x-gtar" | "application/x-tgz" | "application/x-compressed-tar" => {
+                Some(Self::TarGz)
+            }
+            "application/zip" | "application/x-zip-compressed" => Some(Self::Zip),
+            "application/gzip" | "application/x-gzip" => Some(Self::Gz),
+            "application/x-bzip2" => Some(Self::Bz2),
+            "application/x-xz" => Some(Self::Xz),
+            "application/zstd" => Some(Self::Zst),
+            _ => None,
+        }
+    }
+
+    /// Guess the archive type from the file name given by a `Content-Disposition` header,
+    /// then from a `Content-Type` header, and as a last resort from the extension of the
+    /// resource, ignoring any query string.
+    pub(crate) fn guess(
+        filename: Option<&str>,
+        content_type: Option<&str>,
+        resource: &str,
+    ) -> Option<Self> {
+        filename
+            .and_then(|filename| Self::parse_from_extension(filename).ok())
+            .or_else(|| content_type.and_then(Self::parse_from_content_type))
+            .or_else(|| match reqwest::Url::parse(resource) {
+                Ok(url) if url.has_host() => Self::parse_from_extension(url.path()).ok(),
+                _ => Self::parse_from_extension(resource).ok(),
+            })
+    }
+
+    /// Detect the archive type of the file at `path` from its magic bytes.
+    ///
+    /// Compressed files are peeked into to tell tar archives apart from single compressed
+    /// files. The `hint` is only used when the contents are inconclusive, for example
+    /// with tar archives in the old pre-POSIX format, which have no magic bytes.
+    pub(crate) fn detect(path: &Path, hint: Option<Self>) -> Result<Self, Error> {
+        let header = read_header(File::open(path)?)?;
+        let compressed = if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            return Ok(Self::Zip);
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Self::Gz
+        } else if header.starts_with(b"BZh") {
+            Self::Bz2
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::Xz
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Zst
+        } else if is_tar(&header) {
+            return Ok(Self::Tar);
         } else {
-            Err(Error::ExtractionError("unsupported archive format".into()))
+            return hint
+                .ok_or_else(|| Error::invalid_archive("unable to determine archive format"));
+        };
+
+        let tar = match compressed {
+            Self::Gz => Self::TarGz,
+            Self::Bz2 => Self::TarBz2,
+            Self::Xz => Self::TarXz,
+            _ => Self::TarZst,
+        };
+        let decompressed_header = read_header(compressed.decoder(File::open(path)?)?)?;
+        if is_tar(&decompressed_header) || hint == Some(tar) {
+            Ok(tar)
+        } else {
+            Ok(compressed)
         }
     }
+
+    /// Whether this format is a single compressed file, which decompresses
+    /// to a file instead of a directory.
+    pub(crate) fn is_single_file(&self) -> bool {
+        matches!(self, Self::Gz | Self::Bz2 | Self::Xz | Self::Zst)
+    }
+
+    /// Wrap a reader of this format with the corresponding decompressor.
+    fn decoder<'a, R: Read + 'a>(&self, reader: R) -> Result<Box<dyn Read + 'a>, Error> {
+        Ok(match self {
+            Self::Tar => Box::new(reader),
+            Self::TarGz => Box::new(GzDecoder::new(reader)),
+            Self::Gz => Box::new(MultiGzDecoder::new(reader)),
+            Self::TarBz2 | Self::Bz2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+            Self::TarXz | Self::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)),
+            Self::TarZst | Self::Zst => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            Self::Zip => unreachable!("zip archives aren't streamed"),
+        })
+    }
 }
 
+impl FromStr for ArchiveFormat {
+    type Err = Error;
+
+    /// Parse an archive format from its file extension, like `tar.gz` or `zip`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let extension = format!(".{}", s.trim_start_matches('.').to_lowercase());
+        EXTENSIONS
+            .iter()
+            .find(|(e, _)| *e == extension)
+            .map(|(_, format)| *format)
+            .ok_or_else(|| Error::invalid_archive(format!("unsupported archive format '{}'", s)))
+    }
+}
+
+/// Read up to the first 512 bytes, the size of a tar header block.
+fn read_header<R: Read>(reader: R) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(512);
+    reader.take(512).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Check for the magic string of POSIX tar headers.
+fn is_tar(header: &[u8]) -> bool {
+    header.len() >= 262 && &header[257..262] == b"ustar"
+}
+
+/// Extract the archive at `path` to `target`, enforcing the given policy.
+///
+/// `target` will be a directory, unless the format is a single compressed file, in which
+/// case the decompressed file is written to `target`. If `members` is given, only the
+/// entries that match any of the patterns are extracted.
 pub(crate) fn extract_archive<P: AsRef<Path>>(
     path: P,
     target: P,
     format: &ArchiveFormat,
+    policy: &ExtractionPolicy,
+    members: Option<&[Pattern]>,
 ) -> Result<(), Error> {
-    // We'll first extract to a temp directory in the same parent as the target directory.
+    // We'll first extract to a temp directory (or file) in the same parent as the target.
     let target_parent_dir = target.as_ref().parent().unwrap();
+    let tracker = Tracker::new(policy);
+
+    if format.is_single_file() {
+        let mut temp_target = NamedTempFile::new_in(target_parent_dir)?;
+        tracker.add_entry()?;
+        let decoder = format.decoder(tracker.compressed(File::open(path)?))?;
+        io::copy(&mut tracker.uncompressed(decoder), &mut temp_target)
+            .map_err(|e| tracker.into_error(e.into()))?;
+        temp_target
+            .persist(target)
+            .map_err(|e| Error::IoError(e.error))?;
+        return Ok(());
+    }
+
     let temp_target = tempdir_in(target_parent_dir)?;
 
     match format {
-        ArchiveFormat::TarGz => {
-            let tar_gz = File::open(path)?;
-            let tar = GzDecoder::new(tar_gz);
-            let mut archive = tar::Archive::new(tar);
-            archive.unpack(&temp_target)?;
-        }
         ArchiveFormat::Zip => {
-            let file = File::open(path)?;
-            let mut archive =
-                zip::ZipArchive::new(file).map_err(|e| Error::ExtractionError(e.to_string()))?;
-            archive
-                .extract(temp_target.path())
-                .map_err(|e| Error::ExtractionError(e.to_string()))?;
+            unpack_zip(path.as_ref(), temp_target.path(), policy, members, &tracker)
         }
-    };
+        _ => {
+            let decoder = format.decoder(tracker.compressed(File::open(path)?))?;
+            unpack_tar(
+                tracker.uncompressed(decoder),
+                temp_target.path(),
+                policy,
+                members,
+                &tracker,
+            )
+        }
+    }
+    .map_err(|e| tracker.into_error(e))?;
 
     // Now rename the temp directory to the final target directory.
     fs::rename(temp_target, target)?;
 
     Ok(())
 }
+
+/// Unpack a tar archive into `target` one entry at a time, checking each entry against
+/// the policy before it's written.
+fn unpack_tar<R: Read>(
+    reader: R,
+    target: &Path,
+    policy: &ExtractionPolicy,
+    members: Option<&[Pattern]>,
+    tracker: &Tracker,
+) -> Result<(), Error> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        tracker.add_entry()?;
+        let path = entry.path()?.into_owned();
+        policy.check_path(&path)?;
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            if let Some(link_name) = entry.link_name()? {
+                policy.check_link(&path, &link_name, entry_type.is_hard_link())?;
+            }
+        }
+        // The data of skipped entries is read past without being written anywhere.
+        if is_selected(&path, members) {
+            entry.unpack_in(target)?;
+        }
+    }
+    policy.check_extracted_links(target)
+}
+
+/// Unpack a zip archive into `target` one entry at a time, checking each entry against
+/// the policy before it's written.
+///
+/// Like [`zip::ZipArchive::extract`], symbolic links are written as regular files that contain
+/// the target of the link.
+fn unpack_zip(
+    path: &Path,
+    target: &Path,
+    policy: &ExtractionPolicy,
+    members: Option<&[Pattern]>,
+    tracker: &Tracker,
+) -> Result<(), Error> {
+    // The compressed bytes are counted as they're read instead of trusting the sizes in
+    // the headers, which could be forged to get around the compression ratio limit.
+    let mut archive = zip::ZipArchive::new(tracker.compressed(File::open(path)?))
+        .map_err(|e| Error::invalid_archive(e.to_string()))?;
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|e| Error::invalid_archive(e.to_string()))?;
+        tracker.add_entry()?;
+        let path = PathBuf::from(file.name());
+        policy.check_path(&path)?;
+        if !is_selected(&path, members) {
+            continue;
+        }
+
+        let outpath = target.join(&path);
+        if file.is_dir() {
+            fs::create_dir_all(&outpath)?;
+            continue;
+        }
+        if let Some(parent) = outpath.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mode = file.unix_mode();
+        let mut contents = tracker.uncompressed(&mut file);
+        if matches!(mode, Some(mode) if mode & 0o170000 == 0o120000) {
+            let mut link_name = String::new();
+            contents.read_to_string(&mut link_name)?;
+            policy.check_link(&path, Path::new(&link_name), false)?;
+            fs::write(&outpath, link_name)?;
+        } else {
+            io::copy(&mut contents, &mut File::create(&outpath)?)?;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Some(mode) = mode {
+                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode & 0o777))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check if an entry matches any of the patterns given through
+/// [`Options::extract_only()`](crate::Options::extract_only).
+///
+/// Wildcards don't match path separators, so `*.json` only matches files at the root of
+/// the archive while `**/*.json` matches them anywhere.
+fn is_selected(path: &Path, members: Option<&[Pattern]>) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    match members {
+        Some(patterns) => patterns
+            .iter()
+            .any(|pattern| pattern.matches_path_with(path, options)),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_selected() {
+        let patterns = vec![
+            Pattern::new("*.json").unwrap(),
+            Pattern::new("data/**/*.txt").unwrap(),
+        ];
+        assert!(is_selected(Path::new("a.txt"), None));
+        assert!(is_selected(Path::new("config.json"), Some(&patterns)));
+        assert!(!is_selected(
+            Path::new("config/config.json"),
+            Some(&patterns)
+        ));
+        assert!(is_selected(Path::new("data/a.txt"), Some(&patterns)));
+        assert!(is_selected(Path::new("data/a/b.txt"), Some(&patterns)));
+        assert!(!is_selected(Path::new("a.txt"), Some(&patterns)));
+    }
+
+    #[test]
+    fn test_parse_from_extension() {
+        let cases = [
+            ("data.tar", ArchiveFormat::Tar),
+            ("data.tar.gz", ArchiveFormat::TarGz),
+            ("data.TGZ", ArchiveFormat::TarGz),
+            ("data.tar.bz2", ArchiveFormat::TarBz2),
+            ("data.tar.xz", ArchiveFormat::TarXz),
+            ("data.tar.zst", ArchiveFormat::TarZst),
+            ("data.zip", ArchiveFormat::Zip),
+            ("vocab.txt.gz", ArchiveFormat::Gz),
+            ("vocab.txt.bz2", ArchiveFormat::Bz2),
+            ("vocab.txt.xz", ArchiveFormat::Xz),
+            ("vocab.txt.zst", ArchiveFormat::Zst),
+        ];
+        for (resource, expected) in cases.iter() {
+            let format = ArchiveFormat::parse_from_extension(resource).unwrap();
+            assert_eq!(&format, expected);
+        }
+        assert!(ArchiveFormat::parse_from_extension("vocab.txt").is_err());
+        assert!(ArchiveFormat::parse_from_extension("vocab.txt.gz")
+            .unwrap()
+            .is_single_file());
+        assert!(!ArchiveFormat::parse_from_extension("data.tar.gz")
+            .unwrap()
+            .is_single_file());
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(
+            "tar.gz".parse::<ArchiveFormat>().unwrap(),
+            ArchiveFormat::TarGz
+        );
+        assert_eq!(".ZIP".parse::<ArchiveFormat>().unwrap(), ArchiveFormat::Zip);
+        assert_eq!("zst".parse::<ArchiveFormat>().unwrap(), ArchiveFormat::Zst);
+        assert!("gz.tar".parse::<ArchiveFormat>().is_err());
+    }
+
+    #[test]
+    fn test_guess() {
+        assert_eq!(
+            ArchiveFormat::guess(
+                Some("model.tar.gz"),
+                Some("application/octet-stream"),
+                "https://example.com/download?id=1"
+            ),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::guess(
+                None,
+                Some("application/zip; charset=binary"),
+                "https://example.com/model.tar.gz"
+            ),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(
+            ArchiveFormat::guess(None, None, "https://example.com/model.tar.gz?download=1"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::guess(None, None, "data/vocab.txt.xz"),
+            Some(ArchiveFormat::Xz)
+        );
+        assert_eq!(
+            ArchiveFormat::guess(None, None, "https://example.com/download"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_detect() {
+        let cases = [
+            ("utf-8_sample/archives/utf-8.tar", ArchiveFormat::Tar),
+            ("utf-8_sample/archives/utf-8.tar.gz", ArchiveFormat::TarGz),
+            ("utf-8_sample/archives/utf-8.tar.bz2", ArchiveFormat::TarBz2),
+            ("utf-8_sample/archives/utf-8.tar.xz", ArchiveFormat::TarXz),
+            ("utf-8_sample/archives/utf-8.tar.zst", ArchiveFormat::TarZst),
+            ("utf-8_sample/archives/utf-8.zip", ArchiveFormat::Zip),
+            ("hello.txt.gz", ArchiveFormat::Gz),
+            ("hello.txt.xz", ArchiveFormat::Xz),
+            ("hello.txt.zst", ArchiveFormat::Zst),
+        ];
+        for (fixture, expected) in cases.iter() {
+            let path = Path::new("test_fixtures").join(fixture);
+            // The contents take precedence over a misleading hint.
+            let format = ArchiveFormat::detect(&path, Some(ArchiveFormat::Bz2)).unwrap();
+            assert_eq!(&format, expected);
+        }
+
+        // Unrecognized contents fall back to the hint.
+        let path = Path::new("test_fixtures").join("hello.txt");
+        assert!(ArchiveFormat::detect(&path, None).is_err());
+        assert_eq!(
+            ArchiveFormat::detect(&path, Some(ArchiveFormat::Tar)).unwrap(),
+            ArchiveFormat::Tar
+        );
+    }
+}
@@ -0,0 +1,534 @@
+use log::{debug, info, warn};
+use reqwest::{Client, ClientBuilder, Proxy, Response, StatusCode};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use crate::cache::{
+    archive_member_path, is_url, split_archive_member, Cache, CacheBuilder, Location, Options,
+};
+use crate::checksum::{Checksum, HashingWriter};
+use crate::fetch::{self, FetchIo, Fetched};
+use crate::headers::CacheHeaders;
+use crate::remote::{
+    conditional_headers, metadata_from_headers, range_headers, response_offset, RemoteMetadata,
+    RemoteSource,
+};
+use crate::resource::Scheme;
+use crate::{meta::Meta, Error, ProgressBar, ProgressReporter};
+
+/// Builder to facilitate creating [`AsyncCache`] objects.
+///
+/// The settings are the same as those of [`CacheBuilder`], except that the HTTP client
+/// is configured with a non-blocking [`reqwest::ClientBuilder`]. The default sources for
+/// other schemes, like `s3`, are blocking, so they use a blocking client with the timeouts
+/// and proxies set on this builder.
+#[derive(Debug)]
+pub struct AsyncCacheBuilder {
+    cache_builder: CacheBuilder,
+    client_builder: ClientBuilder,
+}
+
+impl AsyncCacheBuilder {
+    /// Construct a new `AsyncCacheBuilder`.
+    pub fn new() -> AsyncCacheBuilder {
+        AsyncCacheBuilder {
+            cache_builder: CacheBuilder::new(),
+            client_builder: ClientBuilder::new(),
+        }
+    }
+
+    /// Construct a new `AsyncCacheBuilder` with a `ClientBuilder`.
+    pub fn with_client_builder(client_builder: ClientBuilder) -> AsyncCacheBuilder {
+        AsyncCacheBuilder::new().client_builder(client_builder)
+    }
+
+    /// Set the cache location. See [`CacheBuilder::dir()`].
+    pub fn dir(mut self, dir: PathBuf) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.dir(dir);
+        self
+    }
+
+    /// Set the `ClientBuilder`.
+    pub fn client_builder(mut self, client_builder: ClientBuilder) -> AsyncCacheBuilder {
+        self.client_builder = client_builder;
+        self
+    }
+
+    /// Enable a request timeout.
+    pub fn timeout(mut self, timeout: Duration) -> AsyncCacheBuilder {
+        self.client_builder = self.client_builder.timeout(timeout);
+        self.cache_builder = self.cache_builder.timeout(timeout);
+        self
+    }
+
+    /// Enable a timeout for the connect phase of each HTTP request.
+    pub fn connect_timeout(mut self, timeout: Duration) -> AsyncCacheBuilder {
+        self.client_builder = self.client_builder.connect_timeout(timeout);
+        self.cache_builder = self.cache_builder.connect_timeout(timeout);
+        self
+    }
+
+    /// Add a proxy for HTTP requests. See [`CacheBuilder::proxy()`].
+    pub fn proxy(mut self, proxy: Proxy) -> AsyncCacheBuilder {
+        self.client_builder = self.client_builder.proxy(proxy.clone());
+        self.cache_builder = self.cache_builder.proxy(proxy);
+        self
+    }
+
+    /// Set maximum number of retries for HTTP requests.
+    pub fn max_retries(mut self, max_retries: u32) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.max_retries(max_retries);
+        self
+    }
+
+    /// Set the maximum backoff delay in milliseconds for retrying HTTP requests.
+    pub fn max_backoff(mut self, max_backoff: u32) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.max_backoff(max_backoff);
+        self
+    }
+
+    /// Set the default freshness lifetime, in seconds.
+    /// See [`CacheBuilder::freshness_lifetime()`].
+    pub fn freshness_lifetime(mut self, freshness_lifetime: u64) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.freshness_lifetime(freshness_lifetime);
+        self
+    }
+
+    /// Only use offline functionality. See [`CacheBuilder::offline()`].
+    pub fn offline(mut self, offline: bool) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.offline(offline);
+        self
+    }
+
+    /// Set the type of progress bar to use.
+    ///
+    /// The default is `Some(ProgressBar::Full)`.
+    pub fn progress_bar(mut self, progress_bar: Option<ProgressBar>) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.progress_bar(progress_bar);
+        self
+    }
+
+    /// Set a [`ProgressReporter`] to receive progress updates for downloads.
+    /// See [`CacheBuilder::progress_reporter()`].
+    pub fn progress_reporter(
+        mut self,
+        progress_reporter: Arc<dyn ProgressReporter>,
+    ) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.progress_reporter(progress_reporter);
+        self
+    }
+
+    /// Set the maximum size, in bytes, of the cache. See [`CacheBuilder::max_size()`].
+    pub fn max_size(mut self, max_size: u64) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.max_size(max_size);
+        self
+    }
+
+    /// Set the number of versions of each remote resource to keep in the cache.
+    /// See [`CacheBuilder::keep_versions()`].
+    pub fn keep_versions(mut self, keep_versions: usize) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.keep_versions(keep_versions);
+        self
+    }
+
+    /// Check the freshness of remote resources with a single conditional GET request.
+    /// See [`CacheBuilder::conditional_get()`].
+    pub fn conditional_get(mut self, conditional_get: bool) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.conditional_get(conditional_get);
+        self
+    }
+
+    /// Copy local files into the cache instead of using them in place.
+    /// See [`CacheBuilder::copy_local_files()`].
+    pub fn copy_local_files(mut self, copy_local_files: bool) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.copy_local_files(copy_local_files);
+        self
+    }
+
+    /// Register a [`RemoteSource`] for resources with the given URL scheme.
+    /// See [`CacheBuilder::remote_source()`].
+    ///
+    /// Sources are blocking, so they're run on tokio's blocking thread pool. Registering
+    /// a source for `http` or `https` replaces the built-in non-blocking client for that
+    /// scheme.
+    pub fn remote_source(
+        mut self,
+        scheme: &str,
+        source: Arc<dyn RemoteSource>,
+    ) -> AsyncCacheBuilder {
+        self.cache_builder = self.cache_builder.remote_source(scheme, source);
+        self
+    }
+
+    /// Build the `AsyncCache` object.
+    pub fn build(self) -> Result<AsyncCache, Error> {
+        let http_client = self.client_builder.build()?;
+        let cache = self.cache_builder.build_with_client(None)?;
+        Ok(AsyncCache { cache, http_client })
+    }
+}
+
+impl Default for AsyncCacheBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An async version of [`Cache`].
+///
+/// Resources are downloaded with a non-blocking HTTP client and written with async
+/// file I/O, while the work that only touches the local file system, like acquiring
+/// locks, reading and writing meta files, and extracting archives, runs on tokio's
+/// blocking thread pool. The on-disk layout of the cache is the same as that of [`Cache`],
+/// so both can be used with the same cache directory.
+///
+/// Resources with the scheme of a registered [`RemoteSource`], including `http` and
+/// `https` if a source is registered for them, are fetched through the source on the
+/// blocking thread pool instead.
+///
+/// This requires the `async` feature and has to be used within a tokio runtime.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use cached_path::AsyncCache;
+/// # async fn run() -> Result<(), cached_path::Error> {
+/// let cache = AsyncCache::new()?;
+/// let path = cache
+///     .cached_path("https://github.com/epwalsh/rust-cached-path/blob/main/README.md")
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AsyncCache {
+    cache: Cache,
+    http_client: Client,
+}
+
+impl AsyncCache {
+    /// Create a new `AsyncCache` instance.
+    pub fn new() -> Result<Self, Error> {
+        AsyncCache::builder().build()
+    }
+
+    /// Create an `AsyncCacheBuilder`.
+    pub fn builder() -> AsyncCacheBuilder {
+        AsyncCacheBuilder::new()
+    }
+
+    /// The root directory of the cache.
+    pub fn dir(&self) -> &Path {
+        &self.cache.dir
+    }
+
+    /// Get the cached path to a resource. See [`Cache::cached_path()`].
+    pub async fn cached_path(&self, resource: &str) -> Result<PathBuf, Error> {
+        self.cached_path_with_options(resource, &Options::default())
+            .await
+    }
+
+    /// Get the cached path to a resource using the given options.
+    /// See [`Cache::cached_path_with_options()`].
+    pub async fn cached_path_with_options(
+        &self,
+        resource: &str,
+        options: &Options,
+    ) -> Result<PathBuf, Error> {
+        if let Some((archive, member)) = split_archive_member(resource) {
+            return match self
+                .cached_resource(archive, &options.clone().extract())
+                .await
+            {
+                Ok(dirpath) => archive_member_path(resource, &dirpath, member),
+                Err(err) if is_url(archive) => self
+                    .cached_resource(resource, options)
+                    .await
+                    .map_err(|_| err),
+                Err(err) => Err(err),
+            };
+        }
+        self.cached_resource(resource, options).await
+    }
+
+    /// Get the cached path to a resource that isn't a member of an archive.
+    async fn cached_resource(&self, resource: &str, options: &Options) -> Result<PathBuf, Error> {
+        let checksum = options
+            .checksum
+            .as_deref()
+            .map(Checksum::parse)
+            .transpose()?;
+        options.member_patterns()?;
+
+        let (cached_path, extraction_dir, meta) = match self.cache.locate(resource)? {
+            Location::Local(path) => {
+                let resource = resource.to_string();
+                let options = options.clone();
+                let (cached_path, extraction_dir) = self
+                    .blocking(move |cache| {
+                        cache.resolve_local_resource(&resource, path, &options, checksum.as_ref())
+                    })
+                    .await?;
+                (cached_path, extraction_dir, None)
+            }
+            Location::Unsupported(scheme) if !matches!(scheme, Scheme::Http | Scheme::Https) => {
+                return Err(Error::UnsupportedScheme(scheme.as_str().into()));
+            }
+            location => {
+                // This is a remote resource, so fetch it to the cache.
+                let meta = if let Location::Source(source, key) = location {
+                    // Registered sources are blocking, so they're run on the blocking thread pool.
+                    let subdir = options.subdir.clone();
+                    self.blocking(move |cache| {
+                        cache.fetch_remote_resource(
+                            source.as_ref(),
+                            &key,
+                            subdir.as_deref(),
+                            checksum.as_ref(),
+                        )
+                    })
+                    .await?
+                } else {
+                    self.fetch_remote_resource(
+                        resource,
+                        options.subdir.as_deref(),
+                        checksum.as_ref(),
+                    )
+                    .await?
+                };
+                let extraction_dir = if options.extract {
+                    Some(meta.extraction_path_with_suffix(&options.extraction_suffix()))
+                } else {
+                    None
+                };
+                (meta.resource_path.clone(), extraction_dir, Some(meta))
+            }
+        };
+
+        if let Some(dirpath) = extraction_dir {
+            let resource = resource.to_string();
+            let extraction_dirpath = dirpath.clone();
+            let options = options.clone();
+            self.blocking(move |cache| {
+                cache.extract_resource(
+                    &resource,
+                    &cached_path,
+                    &extraction_dirpath,
+                    &options,
+                    meta.as_ref(),
+                )
+            })
+            .await?;
+            Ok(dirpath)
+        } else {
+            Ok(cached_path)
+        }
+    }
+
+    async fn fetch_remote_resource(
+        &self,
+        resource: &str,
+        subdir: Option<&str>,
+        checksum: Option<&Checksum>,
+    ) -> Result<Meta, Error> {
+        let url =
+            reqwest::Url::parse(resource).map_err(|_| Error::InvalidUrl(String::from(resource)))?;
+        let io = HttpIo { cache: self, url };
+        fetch::fetch_remote_resource(&self.cache, &io, resource, subdir, checksum).await
+    }
+
+    /// Run a blocking function with the underlying [`Cache`] on tokio's blocking thread pool.
+    async fn blocking<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Cache) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let cache = self.cache.clone();
+        match tokio::task::spawn_blocking(move || f(&cache)).await {
+            Ok(result) => result,
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(err) => Err(io::Error::other(err).into()),
+        }
+    }
+}
+
+/// The non-blocking I/O of [`AsyncCache`], which fetches an `http` or `https` resource
+/// with its own client.
+struct HttpIo<'a> {
+    cache: &'a AsyncCache,
+    url: reqwest::Url,
+}
+
+/// A response to a request for a resource, whose body starts at `offset` within it.
+struct ResponseBody {
+    response: Response,
+    offset: u64,
+}
+
+impl FetchIo for HttpIo<'_> {
+    type Body = ResponseBody;
+
+    async fn local<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Cache) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.cache.blocking(f).await
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+
+    async fn metadata(&self, _resource: &str) -> Result<RemoteMetadata, Error> {
+        debug!("Fetching ETAG for {}", self.url);
+        let response = self
+            .cache
+            .http_client
+            .head(self.url.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(metadata_from_headers(&self.url, response.headers()))
+    }
+
+    async fn fetch(
+        &self,
+        _resource: &str,
+        metadata: &RemoteMetadata,
+        offset: u64,
+    ) -> Result<ResponseBody, Error> {
+        let client = &self.cache.http_client;
+        debug!("Attempting connection to {}", self.url);
+
+        let mut response = client
+            .get(self.url.clone())
+            .headers(range_headers(&self.url, &metadata.headers, offset))
+            .send()
+            .await?;
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // The partial file is no good, so start over.
+            warn!("Unable to resume download of {}, starting over", self.url);
+            response = client.get(self.url.clone()).send().await?;
+        }
+        let response = response.error_for_status()?;
+
+        debug!("Opened connection to {}", self.url);
+
+        let offset = response_offset(response.status(), offset);
+        Ok(ResponseBody { response, offset })
+    }
+
+    async fn fetch_if_changed(
+        &self,
+        _resource: &str,
+        latest: Option<&Meta>,
+    ) -> Result<Option<Fetched<ResponseBody>>, Error> {
+        debug!("Sending conditional request for {}", self.url);
+
+        let response = self
+            .cache
+            .http_client
+            .get(self.url.clone())
+            .headers(conditional_headers(latest))
+            .send()
+            .await?
+            .error_for_status()?;
+        let metadata = RemoteMetadata::from_headers(CacheHeaders::from_headers(response.headers()));
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            Ok(Some(Fetched::NotModified(metadata)))
+        } else {
+            let body = ResponseBody {
+                response,
+                offset: 0,
+            };
+            Ok(Some(Fetched::Modified(metadata, body)))
+        }
+    }
+
+    async fn save_body(
+        &self,
+        resource: &str,
+        body: ResponseBody,
+        path: &Path,
+        metadata: &RemoteMetadata,
+        checksum: Option<&Checksum>,
+        resume_from: u64,
+    ) -> Result<Meta, Error> {
+        let ResponseBody {
+            mut response,
+            offset,
+        } = body;
+        let partial_path = Cache::partial_path(path);
+        let resume_from = Cache::start_offset(resource, offset, resume_from)?;
+        let mut partial_file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume_from > 0)
+            .truncate(resume_from == 0)
+            .open(&partial_path)
+            .await?;
+
+        info!("Starting download of {}", resource);
+
+        // If we have an expected checksum, we hash the contents as we go,
+        // starting with anything downloaded previously.
+        let mut hashing_writer = {
+            let checksum = checksum.cloned();
+            let partial_path = partial_path.clone();
+            self.local(move |_| {
+                Cache::hashing_writer(io::sink(), checksum.as_ref(), &partial_path, resume_from)
+            })
+            .await?
+        };
+        let mut download_wrapper =
+            self.cache
+                .cache
+                .wrap_download(resource, response.content_length(), io::sink());
+
+        let mut bytes: u64 = 0;
+        let result: Result<(), Error> = async {
+            while let Some(chunk) = response.chunk().await? {
+                partial_file.write_all(&chunk).await?;
+                if let Some(hashing_writer) = &mut hashing_writer {
+                    hashing_writer.write_all(&chunk)?;
+                }
+                download_wrapper.write_all(&chunk)?;
+                bytes += chunk.len() as u64;
+            }
+            partial_file.flush().await?;
+            Ok(())
+        }
+        .await;
+        drop(partial_file);
+        if let Err(err) = result {
+            download_wrapper.error(&err);
+            return Err(err);
+        }
+        download_wrapper.finish();
+        let bytes = resume_from + bytes;
+
+        info!("Downloaded {} bytes", bytes);
+
+        let actual_checksum = hashing_writer.map(HashingWriter::finish);
+        let resource = resource.to_string();
+        let path = path.to_path_buf();
+        let headers = metadata.headers.clone();
+        let checksum = checksum.cloned();
+        self.local(move |cache| {
+            cache.finish_download(
+                &resource,
+                &path,
+                &headers,
+                checksum.as_ref(),
+                actual_checksum,
+                bytes,
+            )
+        })
+        .await
+    }
+}
@@ -1,21 +1,32 @@
-
 use fs2::FileExt;
 use glob::glob;
 use log::{debug, error, info, warn};
 use rand::distributions::{Distribution, Uniform};
 use reqwest::blocking::{Client, ClientBuilder};
-use reqwest::header::ETAG;
+use reqwest::Proxy;
+use std::collections::HashMap;
 use std::default::Default;
 use std::env;
-use std::fs::{self, OpenOptions};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{self, Duration};
-use tempfile::NamedTempFile;
-
-use crate::archives::{extract_archive, ArchiveFormat};
+use std::time::Duration;
+
+use crate::archives::extract_archive;
+use crate::checksum::{Checksum, HashingWriter};
+use crate::fetch::{self, block_on, SourceIo};
+use crate::headers::CacheHeaders;
+use crate::progress_bar::{wrap_download, DownloadWrapper};
+use crate::remote::{HttpSource, LocalSource, RemoteBody, RemoteMetadata, RemoteSource};
+use crate::resource::{Resource, Scheme};
 use crate::utils::hash_str;
-use crate::{meta::Meta, Error, ProgressBar};
+use crate::{
+    meta::Meta, ArchiveFormat, Error, ExtractionPolicy, GcsSource, HfSource, ProgressBar,
+    ProgressReporter, S3Source,
+};
 
 /// Builder to facilitate creating [`Cache`] objects.
 #[derive(Debug)]
@@ -27,11 +38,21 @@ pub struct CacheBuilder {
 struct Config {
     dir: Option<PathBuf>,
     client_builder: ClientBuilder,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    proxies: Vec<Proxy>,
     max_retries: u32,
     max_backoff: u32,
     freshness_lifetime: Option<u64>,
     offline: bool,
     progress_bar: Option<ProgressBar>,
+    progress_reporter: Option<Arc<dyn ProgressReporter>>,
+    max_size: Option<u64>,
+    keep_versions: Option<usize>,
+    conditional_get: bool,
+    prefetch_parallelism: usize,
+    copy_local_files: bool,
+    remote_sources: HashMap<String, Arc<dyn RemoteSource>>,
 }
 
 impl CacheBuilder {
@@ -41,11 +62,21 @@ impl CacheBuilder {
             config: Config {
                 dir: None,
                 client_builder: ClientBuilder::new().timeout(None),
+                timeout: None,
+                connect_timeout: None,
+                proxies: vec![],
                 max_retries: 3,
                 max_backoff: 5000,
                 freshness_lifetime: None,
                 offline: false,
                 progress_bar: Some(ProgressBar::default()),
+                progress_reporter: None,
+                max_size: None,
+                keep_versions: None,
+                conditional_get: false,
+                prefetch_parallelism: 4,
+                copy_local_files: false,
+                remote_sources: HashMap::new(),
             },
         }
     }
@@ -72,12 +103,26 @@ impl CacheBuilder {
     /// Enable a request timeout.
     pub fn timeout(mut self, timeout: Duration) -> CacheBuilder {
         self.config.client_builder = self.config.client_builder.timeout(timeout);
+        self.config.timeout = Some(timeout);
         self
     }
 
     /// Enable a timeout for the connect phase of each HTTP request.
     pub fn connect_timeout(mut self, timeout: Duration) -> CacheBuilder {
         self.config.client_builder = self.config.client_builder.connect_timeout(timeout);
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Add a proxy for HTTP requests.
+    ///
+    /// Unlike proxies added to a custom `ClientBuilder`, this also applies to the client
+    /// of the default [`HfSource`], which is built separately since it handles redirects
+    /// itself. The same goes for [`timeout()`](CacheBuilder::timeout) and
+    /// [`connect_timeout()`](CacheBuilder::connect_timeout).
+    pub fn proxy(mut self, proxy: Proxy) -> CacheBuilder {
+        self.config.client_builder = self.config.client_builder.proxy(proxy.clone());
+        self.config.proxies.push(proxy);
         self
     }
 
@@ -94,7 +139,11 @@ impl CacheBuilder {
     }
 
     /// Set the default freshness lifetime, in seconds. The default is None, meaning
-    /// the ETAG for an external resource will always be checked for a fresher value.
+    /// the ETAG for an external resource will always be checked for a fresher value,
+    /// unless the `Cache-Control` or `Expires` headers sent by the server say otherwise.
+    ///
+    /// This acts as a floor: resources are always regarded as fresh within this
+    /// lifetime, even if the server's headers say they expire sooner.
     pub fn freshness_lifetime(mut self, freshness_lifetime: u64) -> CacheBuilder {
         self.config.freshness_lifetime = Some(freshness_lifetime);
         self
@@ -119,8 +168,112 @@ impl CacheBuilder {
         self
     }
 
+    /// Set a [`ProgressReporter`] to receive progress updates for downloads.
+    ///
+    /// This takes precedence over the [`progress_bar()`](CacheBuilder::progress_bar) setting.
+    pub fn progress_reporter(
+        mut self,
+        progress_reporter: Arc<dyn ProgressReporter>,
+    ) -> CacheBuilder {
+        self.config.progress_reporter = Some(progress_reporter);
+        self
+    }
+
+    /// Set the maximum size, in bytes, of the cache.
+    ///
+    /// After a resource is downloaded or extracted, the least-recently-used resources
+    /// are evicted from the cache until its total size is under this limit.
+    /// Resources that are locked by another process are never evicted.
+    ///
+    /// Local archives are extracted into the cache without a meta file, so their extraction
+    /// directories don't count toward this limit and are never evicted. Enable
+    /// [`copy_local_files()`](CacheBuilder::copy_local_files) to have local archives
+    /// tracked like remote resources.
+    ///
+    /// The default is `None`, meaning the cache can grow without bound.
+    pub fn max_size(mut self, max_size: u64) -> CacheBuilder {
+        self.config.max_size = Some(max_size);
+        self
+    }
+
+    /// Set the number of versions of each remote resource to keep in the cache.
+    ///
+    /// When a resource changes (according to its ETAG), a new version is cached alongside
+    /// the old ones. If this is set, all but the newest `keep_versions` versions of a resource
+    /// are removed, along with their meta files and extraction directories.
+    /// At least one version is always kept.
+    ///
+    /// The default is `None`, meaning old versions are never removed.
+    pub fn keep_versions(mut self, keep_versions: usize) -> CacheBuilder {
+        self.config.keep_versions = Some(keep_versions);
+        self
+    }
+
+    /// Check the freshness of remote resources with a single conditional GET request
+    /// instead of a HEAD request followed by a GET request.
+    ///
+    /// If set to `true`, the request carries `If-None-Match` and `If-Modified-Since`
+    /// headers from the latest cached version of the resource. A `304 Not Modified`
+    /// response means the cached version is current, otherwise the response body is
+    /// cached as the new version. This is useful for servers that reject HEAD requests
+    /// or return different ETAGs for them, such as presigned S3 URLs.
+    ///
+    /// The default is `false`.
+    pub fn conditional_get(mut self, conditional_get: bool) -> CacheBuilder {
+        self.config.conditional_get = conditional_get;
+        self
+    }
+
+    /// Set the maximum number of resources that [`Cache::prefetch()`] fetches in parallel.
+    ///
+    /// The default is 4.
+    pub fn prefetch_parallelism(mut self, prefetch_parallelism: usize) -> CacheBuilder {
+        self.config.prefetch_parallelism = std::cmp::max(prefetch_parallelism, 1);
+        self
+    }
+
+    /// Copy local files into the cache instead of using them in place.
+    ///
+    /// This is useful for files on slow network mounts, like NFS. Copies are versioned by
+    /// the modification time and size of the file, so a file is copied again when it
+    /// changes, and otherwise only its metadata is read. Copies are handled like remote
+    /// resources, so they're subject to [`freshness_lifetime()`](CacheBuilder::freshness_lifetime),
+    /// [`offline()`](CacheBuilder::offline), [`max_size()`](CacheBuilder::max_size), and
+    /// [`keep_versions()`](CacheBuilder::keep_versions).
+    ///
+    /// The default is `false`.
+    pub fn copy_local_files(mut self, copy_local_files: bool) -> CacheBuilder {
+        self.config.copy_local_files = copy_local_files;
+        self
+    }
+
+    /// Register a [`RemoteSource`] for resources with the given URL scheme, like `s3`
+    /// for resources like `s3://bucket/key`.
+    ///
+    /// This replaces any source that was already registered for the scheme, including
+    /// the built-in sources for `http` and `https`. `file` URLs always refer to local files.
+    pub fn remote_source(mut self, scheme: &str, source: Arc<dyn RemoteSource>) -> CacheBuilder {
+        self.config
+            .remote_sources
+            .insert(scheme.to_ascii_lowercase(), source);
+        self
+    }
+
     /// Build the `Cache` object.
-    pub fn build(self) -> Result<Cache, Error> {
+    pub fn build(mut self) -> Result<Cache, Error> {
+        let http_client = std::mem::take(&mut self.config.client_builder).build()?;
+        self.build_with_client(Some(http_client))
+    }
+
+    /// Build the `Cache` object with the given HTTP client, ignoring the client builder.
+    ///
+    /// Without a client, the default sources for `http` and `https` aren't registered, so
+    /// those resources are only fetched through sources that were registered explicitly.
+    /// The default sources for `s3`, `gs`, and `hf` are registered either way, with a client
+    /// built from the client builder if none is given.
+    pub(crate) fn build_with_client(self, http_client: Option<Client>) -> Result<Cache, Error> {
+        let hf_client_builder = self.config.source_client_builder();
+        let client_builder = self.config.client_builder;
         let dir = self.config.dir.unwrap_or_else(|| {
             if let Some(dir_str) = env::var_os("RUST_CACHED_PATH_ROOT") {
                 PathBuf::from(dir_str)
@@ -128,33 +281,124 @@ impl CacheBuilder {
                 env::temp_dir().join("cache/")
             }
         });
-        let http_client = self.config.client_builder.build()?;
         fs::create_dir_all(&dir)?;
+        let mut remote_sources = self.config.remote_sources;
+
+        let register_http = http_client.is_some();
+        let needs_hf = !remote_sources.contains_key("hf");
+        let build_clients = move || -> Result<(Client, Option<HfSource>), Error> {
+            let http_client = match http_client {
+                Some(http_client) => http_client,
+                None => client_builder.build()?,
+            };
+            let hf_source = if needs_hf {
+                Some(HfSource::with_client_builder(hf_client_builder)?)
+            } else {
+                None
+            };
+            Ok((http_client, hf_source))
+        };
+        let (http_client, hf_source) = if register_http {
+            build_clients()?
+        } else {
+            // Blocking clients can't be built within an async runtime, which is where the
+            // cache of an `AsyncCache` might be built, so they're built on another thread.
+            thread::spawn(build_clients)
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?
+        };
+
+        if register_http {
+            let http_source: Arc<dyn RemoteSource> = Arc::new(HttpSource::new(http_client.clone()));
+            for scheme in &["http", "https"] {
+                remote_sources
+                    .entry(scheme.to_string())
+                    .or_insert_with(|| http_source.clone());
+            }
+        }
+        remote_sources
+            .entry("gs".into())
+            .or_insert_with(|| Arc::new(GcsSource::with_client(http_client.clone())));
+        remote_sources
+            .entry("s3".into())
+            .or_insert_with(|| Arc::new(S3Source::with_client(http_client)));
+        if let Some(hf_source) = hf_source {
+            remote_sources.insert("hf".into(), Arc::new(hf_source));
+        }
         Ok(Cache {
             dir,
-            http_client,
+            remote_sources,
             max_retries: self.config.max_retries,
             max_backoff: self.config.max_backoff,
             freshness_lifetime: self.config.freshness_lifetime,
             offline: self.config.offline,
             progress_bar: self.config.progress_bar,
+            progress_reporter: self.config.progress_reporter,
+            max_size: self.config.max_size,
+            keep_versions: self.config.keep_versions,
+            conditional_get: self.config.conditional_get,
+            prefetch_parallelism: self.config.prefetch_parallelism,
+            copy_local_files: self.config.copy_local_files,
         })
     }
 }
 
+impl Config {
+    /// A client builder with the timeouts and proxies set on the `CacheBuilder`, for
+    /// default sources that can't share the client built from `client_builder`.
+    fn source_client_builder(&self) -> ClientBuilder {
+        let mut client_builder = ClientBuilder::new().timeout(self.timeout);
+        if let Some(connect_timeout) = self.connect_timeout {
+            client_builder = client_builder.connect_timeout(connect_timeout);
+        }
+        for proxy in &self.proxies {
+            client_builder = client_builder.proxy(proxy.clone());
+        }
+        client_builder
+    }
+}
+
 impl Default for CacheBuilder {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Where to get a resource from, see [`Cache::locate()`].
+pub(crate) enum Location {
+    /// Fetch it through a source, which is given the second field as the resource.
+    Source(Arc<dyn RemoteSource>, String),
+    /// It's a URL with a scheme that no source is registered for.
+    Unsupported(Scheme),
+    /// It's a local file.
+    Local(PathBuf),
+}
+
+/// The state of the cached versions of a remote resource, see [`Cache::check_cached_versions()`].
+pub(crate) enum CachedVersion {
+    /// A cached version can be used without contacting the source.
+    Usable(Meta),
+    /// The resource has to be fetched or revalidated. This is the latest cached version,
+    /// if there is one.
+    Stale(Option<Meta>),
+}
+
 /// Options to use with [`Cache::cached_path_with_options`].
-#[derive(Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Options {
     /// An optional subdirectory (relative to the cache root) to cache the resource in.
     pub subdir: Option<String>,
     /// Automatically extract the resource, assuming the resource is an archive.
     pub extract: bool,
+    /// An optional expected checksum of the resource, in the form `<algorithm>:<hex digest>`.
+    pub checksum: Option<String>,
+    /// An optional archive format to use when extracting the resource instead of
+    /// detecting it.
+    pub archive_format: Option<ArchiveFormat>,
+    /// The rules and limits that archives have to satisfy to be extracted.
+    pub extraction_policy: ExtractionPolicy,
+    /// Optional glob patterns that select which members of an archive are extracted.
+    pub extract_only: Option<Vec<String>>,
 }
 
 impl Options {
@@ -162,6 +406,10 @@ impl Options {
         Self {
             subdir: subdir.map(String::from),
             extract,
+            checksum: None,
+            archive_format: None,
+            extraction_policy: ExtractionPolicy::default(),
+            extract_only: None,
         }
     }
 
@@ -172,10 +420,128 @@ impl Options {
     }
 
     /// Treat the resource as an archive and try to extract it.
+    ///
+    /// Supported formats are `.tar`, `.tar.gz` (or `.tgz`), `.tar.bz2`, `.tar.xz`, `.tar.zst`,
+    /// and `.zip`, which are extracted to a directory, and single files compressed with
+    /// gzip (`.gz`), bzip2 (`.bz2`), xz (`.xz`), or zstd (`.zst`), which are decompressed
+    /// to a file.
+    ///
+    /// The format is detected from the contents of the resource. When the contents are
+    /// inconclusive, the `Content-Disposition` and `Content-Type` headers of remote resources
+    /// are used, followed by the extension of the resource. Use
+    /// [`archive_format()`](Options::archive_format) to skip detection.
     pub fn extract(mut self) -> Self {
         self.extract = true;
         self
     }
+
+    /// Extract the resource as the given archive format instead of detecting it.
+    ///
+    /// This only has an effect together with [`extract()`](Options::extract).
+    pub fn archive_format(mut self, archive_format: ArchiveFormat) -> Self {
+        self.archive_format = Some(archive_format);
+        self
+    }
+
+    /// Only extract the members of the archive that match any of the given glob patterns,
+    /// like `config/*.json` or `**/*.txt`. This implies [`extract()`](Options::extract).
+    ///
+    /// The archive is streamed through and only the matching members are written to disk.
+    /// Every set of patterns is extracted to its own directory, so different selections
+    /// from the same archive don't collide. Single compressed files are always decompressed
+    /// in full.
+    pub fn extract_only<I, S>(mut self, globs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extract = true;
+        self.extract_only = Some(globs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Parse the patterns given through [`extract_only()`](Options::extract_only).
+    pub(crate) fn member_patterns(&self) -> Result<Option<Vec<glob::Pattern>>, Error> {
+        self.extract_only
+            .as_ref()
+            .map(|globs| {
+                globs
+                    .iter()
+                    .map(|g| {
+                        glob::Pattern::new(g)
+                            .map_err(|e| Error::InvalidGlob(format!("{}: {}", g, e)))
+                    })
+                    .collect()
+            })
+            .transpose()
+    }
+
+    /// The suffix of the extraction directory, which is unique to the set of patterns
+    /// given through [`extract_only()`](Options::extract_only).
+    pub(crate) fn extraction_suffix(&self) -> String {
+        match &self.extract_only {
+            Some(globs) => {
+                let mut globs = globs.clone();
+                globs.sort();
+                globs.dedup();
+                format!("-extracted-{}", &hash_str(&globs.join("\n"))[..16])
+            }
+            None => "-extracted".into(),
+        }
+    }
+
+    /// Set the rules and limits that archives have to satisfy to be extracted.
+    ///
+    /// Regardless of the policy, entries with absolute paths or `..` components are never
+    /// extracted. See [`ExtractionPolicy`] for the details.
+    pub fn extraction_policy(mut self, extraction_policy: ExtractionPolicy) -> Self {
+        self.extraction_policy = extraction_policy;
+        self
+    }
+
+    /// Verify the resource against an expected checksum, given in the form
+    /// `<algorithm>:<hex digest>`. The supported algorithms are `sha256`, `sha512`, and `blake3`.
+    ///
+    /// Remote resources are hashed while they're downloaded, and a
+    /// [`ChecksumMismatch`](crate::Error::ChecksumMismatch) error is returned
+    /// if the checksum doesn't match, in which case nothing is cached.
+    /// The verified checksum is recorded in the resource's [`Meta`] so that subsequent
+    /// calls don't have to hash the cached file again.
+    /// Local files are hashed on every call.
+    pub fn checksum(mut self, checksum: &str) -> Self {
+        self.checksum = Some(checksum.into());
+        self
+    }
+}
+
+/// A resource to fetch with [`Cache::prefetch()`], along with the options to use.
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// The resource path.
+    pub resource: String,
+    /// The options to fetch the resource with.
+    pub options: Options,
+}
+
+impl Request {
+    pub fn new(resource: &str) -> Self {
+        Self {
+            resource: resource.into(),
+            options: Options::default(),
+        }
+    }
+
+    /// Set the options to fetch the resource with.
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl From<&str> for Request {
+    fn from(resource: &str) -> Self {
+        Self::new(resource)
+    }
 }
 
 /// Fetches and manages resources in a local cache directory.
@@ -184,23 +550,45 @@ pub struct Cache {
     /// The root directory of the cache.
     pub dir: PathBuf,
     /// The maximum number of times to retry downloading a remote resource.
-    max_retries: u32,
+    pub(crate) max_retries: u32,
     /// The maximum amount of time (in milliseconds) to wait before retrying a download.
-    max_backoff: u32,
+    pub(crate) max_backoff: u32,
     /// An optional freshness lifetime (in seconds).
     ///
     /// If set, resources that were cached within the past `freshness_lifetime` seconds
     /// will always be regarded as fresh, and so the ETag of the corresponding remote
-    /// resource won't be checked.
-    freshness_lifetime: Option<u64>,
+    /// resource won't be checked. Otherwise freshness is determined by the
+    /// `Cache-Control` and `Expires` headers of the resource.
+    pub(crate) freshness_lifetime: Option<u64>,
     /// Offline mode.
     ///
     /// If set to `true`, no HTTP calls will be made.
-    offline: bool,
+    pub(crate) offline: bool,
     /// The verbosity level of the progress bar.
-    progress_bar: Option<ProgressBar>,
-    /// The HTTP client used to fetch remote resources.
-    http_client: Client,
+    pub(crate) progress_bar: Option<ProgressBar>,
+    /// An optional progress reporter, which takes precedence over the progress bar.
+    pub(crate) progress_reporter: Option<Arc<dyn ProgressReporter>>,
+    /// An optional maximum size (in bytes) of the cache.
+    ///
+    /// If set, the least-recently-used resources will be evicted after a new
+    /// resource is downloaded or extracted until the cache is under this size.
+    pub(crate) max_size: Option<u64>,
+    /// An optional number of versions of each resource to keep.
+    ///
+    /// If set, older versions of a resource will be removed when the resource is fetched.
+    pub(crate) keep_versions: Option<usize>,
+    /// Check freshness with a single conditional GET instead of HEAD + GET.
+    pub(crate) conditional_get: bool,
+    /// The maximum number of resources to fetch in parallel in [`Cache::prefetch()`].
+    pub(crate) prefetch_parallelism: usize,
+    /// Copy local files into the cache instead of using them in place.
+    pub(crate) copy_local_files: bool,
+    /// The sources used to fetch remote resources, by URL scheme.
+    ///
+    /// The cache underlying an [`AsyncCache`](crate::AsyncCache) doesn't have the default
+    /// `http` and `https` sources, since the `AsyncCache` has its own non-blocking client
+    /// and a blocking client can't be dropped from within an async runtime.
+    remote_sources: HashMap<String, Arc<dyn RemoteSource>>,
 }
 
 impl Cache {
@@ -218,6 +606,10 @@ impl Cache {
     ///
     /// If the resource is local file, it's path is returned. If the resource is a static HTTP
     /// resource, it will cached locally and the path to the cache file will be returned.
+    ///
+    /// Local files can be given as paths, where a leading `~` is expanded to the home
+    /// directory, or as `file://` URLs. They're copied into the cache too if
+    /// [`copy_local_files()`](CacheBuilder::copy_local_files) is enabled.
     pub fn cached_path(&self, resource: &str) -> Result<PathBuf, Error> {
         self.cached_path_with_options(resource, &Options::default())
     }
@@ -240,7 +632,8 @@ impl Cache {
     /// ```
     ///
     /// Treat the resource as an archive and extract it. The path returned is the
-    /// path to the extraction directory:
+    /// path to the extraction directory, or to the decompressed file if the resource
+    /// is a single compressed file like `vocab.txt.gz`:
     ///
     /// ```rust,no_run
     /// # use cached_path::{Cache, Options};
@@ -253,84 +646,225 @@ impl Cache {
     /// ).unwrap();
     /// assert!(path.is_dir());
     /// ```
+    ///
+    /// Get a single file from inside of an archive by appending `!/` and the path of the
+    /// file within the archive to the resource. The archive is downloaded and extracted
+    /// once, and the path returned is the path to the file within the extraction directory.
+    /// Local paths are only split at the `!/` if the part before it is a file, and URLs are
+    /// fetched as they are if the part before it can't be fetched and extracted:
+    ///
+    /// ```rust,no_run
+    /// # use cached_path::{Cache, Options};
+    /// # let cache = Cache::new().unwrap();
+    /// let path = cache.cached_path_with_options(
+    ///     "https://example.com/bundle.tar.gz!/config/model.json",
+    ///     &Options::default(),
+    /// ).unwrap();
+    /// assert!(path.is_file());
+    /// ```
     pub fn cached_path_with_options(
         &self,
         resource: &str,
         options: &Options,
     ) -> Result<PathBuf, Error> {
-        let cached_path: PathBuf;
-        let mut extraction_dir: Option<PathBuf> = None;
-
-        if !resource.starts_with("http") {
-            // If resource doesn't look like a URL, treat as local path, but return
-            // an error if the path doesn't exist.
-            info!("Treating {} as local file", resource);
-            cached_path = PathBuf::from(resource);
-
-            if !cached_path.is_file() {
-                return Err(Error::ResourceNotFound(String::from(resource)));
-            }
+        if let Some((archive, member)) = split_archive_member(resource) {
+            return match self.cached_resource(archive, &options.clone().extract()) {
+                Ok(dirpath) => archive_member_path(resource, &dirpath, member),
+                Err(err) if is_url(archive) => {
+                    // The `!/` might just be part of the URL. If that doesn't work either,
+                    // the error for the archive is the more useful one.
+                    self.cached_resource(resource, options).map_err(|_| err)
+                }
+                Err(err) => Err(err),
+            };
+        }
+        self.cached_resource(resource, options)
+    }
 
-            if options.extract {
-                // If we need to extract, we extract into a unique subdirectory of the cache directory
-                // so as not to mess with the file system outside of the cache directory.
-                // To make sure that we use a unique directory for each "version" of this local
-                // resource, we treat the last modified time as an ETag.
-                let resource_last_modified = fs::metadata(resource)?
-                    .modified()
-                    .ok()
-                    .and_then(|sys_time| sys_time.elapsed().ok())
-                    .map(|duration| format!("{}", duration.as_secs()));
-                extraction_dir = Some(self.resource_to_filepath(
-                    resource,
-                    &resource_last_modified,
+    /// Get the cached path to a resource that isn't a member of an archive.
+    fn cached_resource(&self, resource: &str, options: &Options) -> Result<PathBuf, Error> {
+        let checksum = options
+            .checksum
+            .as_deref()
+            .map(Checksum::parse)
+            .transpose()?;
+        options.member_patterns()?;
+
+        let (cached_path, extraction_dir, meta) = match self.locate(resource)? {
+            Location::Source(source, key) => {
+                // This is a remote resource, so fetch it to the cache.
+                let meta = self.fetch_remote_resource(
+                    source.as_ref(),
+                    &key,
                     options.subdir.as_deref(),
-                    Some("-extracted"),
-                ));
+                    checksum.as_ref(),
+                )?;
+
+                // Check if we need to extract.
+                let extraction_dir = if options.extract {
+                    Some(meta.extraction_path_with_suffix(&options.extraction_suffix()))
+                } else {
+                    None
+                };
+                (meta.resource_path.clone(), extraction_dir, Some(meta))
+            }
+            Location::Local(path) => {
+                let (path, dir) =
+                    self.resolve_local_resource(resource, path, options, checksum.as_ref())?;
+                (path, dir, None)
             }
+            Location::Unsupported(scheme) => {
+                return Err(Error::UnsupportedScheme(scheme.as_str().into()));
+            }
+        };
+
+        if let Some(dirpath) = extraction_dir {
+            self.extract_resource(resource, &cached_path, &dirpath, options, meta.as_ref())?;
+            Ok(dirpath)
         } else {
-            // This is a remote resource, so fetch it to the cache.
-            let meta = self.fetch_remote_resource(resource, options.subdir.as_deref())?;
+            Ok(cached_path)
+        }
+    }
 
-            // Check if we need to extract.
-            if options.extract {
-                extraction_dir = Some(meta.get_extraction_path());
-            }
+    /// Make sure a local resource exists and matches the expected checksum, if there is one.
+    ///
+    /// Returns the path to the resource along with the directory to extract it to,
+    /// if extraction was requested.
+    pub(crate) fn resolve_local_resource(
+        &self,
+        resource: &str,
+        cached_path: PathBuf,
+        options: &Options,
+        checksum: Option<&Checksum>,
+    ) -> Result<(PathBuf, Option<PathBuf>), Error> {
+        // If resource doesn't look like a URL, treat as local path, but return
+        // an error if the path doesn't exist.
+        info!("Treating {} as local file", resource);
+
+        if !cached_path.is_file() {
+            return Err(Error::ResourceNotFound(String::from(resource)));
+        }
 
-            cached_path = meta.resource_path;
+        if let Some(checksum) = checksum {
+            let actual = checksum.compute_file(&cached_path)?;
+            checksum.verify(resource, &actual)?;
         }
 
-        if let Some(dirpath) = extraction_dir {
-            // Extract archive.
-            debug!("Treating {} as archive", resource);
-
-            fs::create_dir_all(dirpath.parent().unwrap())?;
-
-            // Need to acquire a lock here to make sure we don't try to extract
-            // the same archive in parallel from multiple processes.
-            debug!("Acquiring lock on extraction directory for {}", resource);
-            let lock_path = format!("{}.lock", dirpath.to_str().unwrap());
-            let filelock = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(lock_path)?;
-            filelock.lock_exclusive()?;
-            debug!("Lock on extraction directory acquired for {}", resource);
-
-            if !dirpath.is_dir() {
-                info!("Extracting {} to {:?}", resource, dirpath);
-                let format = ArchiveFormat::parse_from_extension(resource)?;
-                extract_archive(&cached_path, &dirpath, &format)?;
-            }
+        let mut extraction_dir: Option<PathBuf> = None;
+        if options.extract {
+            // If we need to extract, we extract into a unique subdirectory of the cache directory
+            // so as not to mess with the file system outside of the cache directory.
+            // To make sure that we use a unique directory for each "version" of this local
+            // resource, we treat the last modified time and size as an ETag.
+            // No meta file is written for it, so it isn't one of the cache's `entries()` and
+            // doesn't count toward `max_size`.
+            let path = cached_path.to_string_lossy();
+            let version = LocalSource.metadata(&path)?.version();
+            extraction_dir = Some(self.resource_to_filepath(
+                &path,
+                &version,
+                options.subdir.as_deref(),
+                Some(&options.extraction_suffix()),
+            ));
+        }
 
-            filelock.unlock()?;
-            debug!("Lock released on extraction directory for {}", resource);
+        Ok((cached_path, extraction_dir))
+    }
 
-            Ok(dirpath)
-        } else {
-            Ok(cached_path)
+    /// Extract the archive at `cached_path` into `dirpath`, unless it's already been extracted.
+    ///
+    /// For single compressed files, `dirpath` is the path of the decompressed file.
+    /// The `meta` of remote resources provides hints for detecting the archive format.
+    pub(crate) fn extract_resource(
+        &self,
+        resource: &str,
+        cached_path: &Path,
+        dirpath: &Path,
+        options: &Options,
+        meta: Option<&Meta>,
+    ) -> Result<(), Error> {
+        debug!("Treating {} as archive", resource);
+
+        fs::create_dir_all(dirpath.parent().unwrap())?;
+
+        // Need to acquire a lock here to make sure we don't try to extract
+        // the same archive in parallel from multiple processes.
+        debug!("Acquiring lock on extraction directory for {}", resource);
+        let filelock = lock(dirpath)?;
+        debug!("Lock on extraction directory acquired for {}", resource);
+
+        if !dirpath.exists() {
+            let format = match options.archive_format {
+                Some(format) => format,
+                None => {
+                    let hint = ArchiveFormat::guess(
+                        meta.and_then(Meta::filename),
+                        meta.and_then(Meta::content_type),
+                        resource,
+                    );
+                    ArchiveFormat::detect(cached_path, hint)?
+                }
+            };
+            info!("Extracting {} to {:?} as {:?}", resource, dirpath, format);
+            extract_archive(
+                cached_path,
+                dirpath,
+                &format,
+                &options.extraction_policy,
+                options.member_patterns()?.as_deref(),
+            )?;
+            self.evict(cached_path);
         }
+
+        filelock.unlock()?;
+        debug!("Lock released on extraction directory for {}", resource);
+
+        Ok(())
+    }
+
+    /// Get the cached paths to a batch of resources, fetching up to
+    /// [`prefetch_parallelism`](CacheBuilder::prefetch_parallelism) of them in parallel.
+    ///
+    /// This is useful for populating a cache before going offline. A failure to fetch one
+    /// resource doesn't stop the others from being fetched, and the results are returned
+    /// in the same order as the requests.
+    ///
+    /// ```rust,no_run
+    /// # use cached_path::{Cache, Options, Request};
+    /// # let cache = Cache::new().unwrap();
+    /// let results = cache.prefetch(&[
+    ///     Request::new("https://example.com/vocab.txt"),
+    ///     Request::new("https://example.com/model.tar.gz").options(Options::default().extract()),
+    /// ]);
+    /// for result in results {
+    ///     println!("{:?}", result);
+    /// }
+    /// ```
+    pub fn prefetch(&self, requests: &[Request]) -> Vec<Result<PathBuf, Error>> {
+        let next = AtomicUsize::new(0);
+        let results: Vec<Mutex<Option<Result<PathBuf, Error>>>> =
+            requests.iter().map(|_| Mutex::new(None)).collect();
+        let workers = std::cmp::min(self.prefetch_parallelism, requests.len());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let request = match requests.get(i) {
+                        Some(request) => request,
+                        None => break,
+                    };
+                    let result = self.cached_path_with_options(&request.resource, &request.options);
+                    if let Err(err) = &result {
+                        warn!("Failed to prefetch {}: {}", request.resource, err);
+                    }
+                    *results[i].lock().unwrap() = Some(result);
+                });
+            }
+        });
+        results
+            .into_iter()
+            .map(|result| result.into_inner().unwrap().unwrap())
+            .collect()
     }
 
     /// A convenience method to get the cached path to a resource using the given
@@ -361,11 +895,48 @@ impl Cache {
         self.cached_path_with_options(resource, &options)
     }
 
-    fn fetch_remote_resource(&self, resource: &str, subdir: Option<&str>) -> Result<Meta, Error> {
-        // Otherwise we attempt to parse the URL.
-        let url =
-            reqwest::Url::parse(resource).map_err(|_| Error::InvalidUrl(String::from(resource)))?;
+    /// Figure out where to get a resource from.
+    pub(crate) fn locate(&self, resource: &str) -> Result<Location, Error> {
+        Ok(match Resource::parse(resource)? {
+            Resource::Url(scheme) => match self.remote_sources.get(scheme.as_str()) {
+                Some(source) => Location::Source(source.clone(), resource.into()),
+                None => Location::Unsupported(scheme),
+            },
+            Resource::Local(path) if self.copy_local_files => {
+                // Copies are keyed by the absolute path, so that every way of referring
+                // to a file shares them.
+                let path = fs::canonicalize(&path).unwrap_or(path);
+                Location::Source(Arc::new(LocalSource), path.to_string_lossy().into_owned())
+            }
+            Resource::Local(path) => Location::Local(path),
+        })
+    }
+
+    pub(crate) fn fetch_remote_resource(
+        &self,
+        source: &dyn RemoteSource,
+        resource: &str,
+        subdir: Option<&str>,
+        checksum: Option<&Checksum>,
+    ) -> Result<Meta, Error> {
+        let io = SourceIo {
+            cache: self,
+            source,
+        };
+        block_on(fetch::fetch_remote_resource(
+            self, &io, resource, subdir, checksum,
+        ))
+    }
 
+    /// Find the cached versions of a remote resource and check whether the latest one
+    /// can be used as-is, because it's still fresh according to the `freshness_lifetime`
+    /// setting or because the cache is offline.
+    pub(crate) fn check_cached_versions(
+        &self,
+        resource: &str,
+        subdir: Option<&str>,
+        checksum: Option<&Checksum>,
+    ) -> Result<CachedVersion, Error> {
         // Ensure root directory exists in case it has changed or been removed.
         if let Some(subdir_path) = subdir {
             fs::create_dir_all(self.dir.join(subdir_path))?;
@@ -373,63 +944,244 @@ impl Cache {
             fs::create_dir_all(&self.dir)?;
         };
 
-        // Find any existing cached versions of resource and check if they are still
-        // fresh according to the `freshness_lifetime` setting.
-        let versions = self.find_existing(resource, subdir); // already sorted, latest is first.
-        if self.offline {
-            if !versions.is_empty() {
+        let latest = self.find_existing(resource, subdir).into_iter().next(); // latest is first.
+        let latest = match latest {
+            Some(latest) if self.offline => {
                 info!("Found existing cached version of {}", resource);
-                return Ok(versions[0].clone());
-            } else {
+                self.record_access(latest)?
+            }
+            Some(latest) if latest.is_fresh(self.freshness_lifetime) => {
+                // Oh hey, the latest version is still fresh!
+                info!("Latest cached version of {} is still fresh", resource);
+                match self.record_access(latest)? {
+                    Some(meta) => Some(meta),
+                    // It was evicted in the meantime.
+                    None => return Ok(CachedVersion::Stale(None)),
+                }
+            }
+            None if self.offline => None,
+            latest => return Ok(CachedVersion::Stale(latest)),
+        };
+        match latest {
+            Some(meta) => Ok(CachedVersion::Usable(self.verify_cached(meta, checksum)?)),
+            None => {
                 error!("Offline mode is enabled but no cached versions of resource exist.");
-                return Err(Error::NoCachedVersions(String::from(resource)));
+                Err(Error::NoCachedVersions(String::from(resource)))
             }
-        } else if !versions.is_empty() && versions[0].is_fresh(self.freshness_lifetime) {
-            // Oh hey, the latest version is still fresh!
-            info!("Latest cached version of {} is still fresh", resource);
-            return Ok(versions[0].clone());
-        }
-
-        // No existing version or the existing versions are older than their freshness
-        // lifetimes, so we'll query for the ETAG of the resource and then compare
-        // that with any existing versions.
-        let etag = self.try_get_etag(resource, &url)?;
-        let path = self.resource_to_filepath(resource, &etag, subdir, None);
-
-        // Before going further we need to obtain a lock on the file to provide
-        // parallel downloads of the same resource.
-        debug!("Acquiring lock for cache of {}", resource);
-        let lock_path = format!("{}.lock", path.to_str().unwrap());
-        let filelock = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(lock_path)?;
-        filelock.lock_exclusive()?;
-        debug!("Lock acquired for {}", resource);
+        }
+    }
 
-        if path.exists() {
-            // Oh cool! The cache is up-to-date according to the ETAG.
-            // We'll return the up-to-date version and clean up any other
-            // dangling ones.
-            info!("Cached version of {} is up-to-date", resource);
-            filelock.unlock()?;
-            return Meta::from_cache(&path);
+    /// Get the meta of the version of a resource cached at `path`, which is known to be
+    /// up-to-date, updated from the `headers` of the source if there are any, and remove
+    /// any old versions.
+    ///
+    /// This should only be called while holding the lock on `path`.
+    pub(crate) fn up_to_date_version(
+        &self,
+        resource: &str,
+        subdir: Option<&str>,
+        path: &Path,
+        headers: Option<&CacheHeaders>,
+        checksum: Option<&Checksum>,
+    ) -> Result<Meta, Error> {
+        self.remove_old_versions(resource, subdir, path);
+        let mut meta = Meta::from_cache(path)?;
+        if let Some(headers) = headers {
+            meta.update_from_headers(headers);
+        }
+        let meta = self.verify_cached(meta, checksum)?;
+        self.save_meta(meta, headers.is_some())
+            .ok_or_else(|| Error::CacheCorrupted(format!("missing resource at {:?}", path)))
+    }
+
+    /// Get the meta of the `latest` cached version of a resource after the source
+    /// reported that it hasn't been modified.
+    pub(crate) fn not_modified_version(
+        &self,
+        resource: &str,
+        latest: Option<&Meta>,
+        headers: &CacheHeaders,
+        checksum: Option<&Checksum>,
+    ) -> Result<Meta, Error> {
+        match latest {
+            Some(latest) => {
+                info!("Cached version of {} is up-to-date", resource);
+                let mut meta = latest.clone();
+                meta.update_from_headers(headers);
+                let filelock = lock(&meta.resource_path)?;
+                let meta = self.save_meta(meta, true);
+                filelock.unlock()?;
+                match meta {
+                    Some(meta) => self.verify_cached(meta, checksum),
+                    // It was evicted in the meantime.
+                    None => Err(Error::NoCachedVersions(String::from(resource))),
+                }
+            }
+            None => Err(Error::NoCachedVersions(String::from(resource))),
+        }
+    }
+
+    /// Remove old versions of a resource and make room for the version newly cached
+    /// at `path` if needed.
+    ///
+    /// This should only be called while holding the lock on `path`, so that the new
+    /// version won't be evicted.
+    pub(crate) fn clean_up(&self, resource: &str, subdir: Option<&str>, path: &Path) {
+        self.remove_old_versions(resource, subdir, path);
+        self.evict(path);
+    }
+
+    /// Iterate over the [`Meta`] of every resource in the cache, including resources cached
+    /// in subdirectories of the cache root.
+    ///
+    /// Meta files that can't be read are skipped. The extraction directories of local
+    /// archives don't have meta files, so they aren't included.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use cached_path::Cache;
+    /// # let cache = Cache::new().unwrap();
+    /// for meta in cache.entries() {
+    ///     println!("{} -> {:?}", meta.resource(), meta.resource_path());
+    /// }
+    /// ```
+    pub fn entries(&self) -> impl Iterator<Item = Meta> {
+        let mut meta_paths: Vec<PathBuf> = vec![];
+        find_meta_files(&self.dir, &mut meta_paths);
+        meta_paths.sort();
+        meta_paths
+            .into_iter()
+            .filter_map(|meta_path| Meta::from_path(&meta_path).ok())
+    }
+
+    /// Write the cached versions of the given resources to a tar bundle, which can be
+    /// imported into another cache with [`import()`](Cache::import).
+    ///
+    /// The bundle contains the cached files along with their meta files, at the same
+    /// paths relative to the cache root. Extraction directories aren't included, since
+    /// archives are extracted again on demand. If `resources` is empty, every resource
+    /// in the cache is exported.
+    ///
+    /// Returns the [`Meta`] of every version that was exported, or a
+    /// [`NoCachedVersions`](Error::NoCachedVersions) error if one of the resources
+    /// isn't cached.
+    ///
+    /// ```rust,no_run
+    /// # use cached_path::Cache;
+    /// # use std::fs::File;
+    /// # let cache = Cache::new().unwrap();
+    /// let bundle = File::create("bundle.tar").unwrap();
+    /// cache.export(&["https://example.com/model.tar.gz"], bundle).unwrap();
+    /// ```
+    pub fn export<W: Write>(&self, resources: &[&str], writer: W) -> Result<Vec<Meta>, Error> {
+        let entries: Vec<Meta> = self
+            .entries()
+            .filter(|meta| resources.is_empty() || resources.contains(&meta.resource.as_str()))
+            .collect();
+        for resource in resources {
+            if !entries.iter().any(|meta| meta.resource == *resource) {
+                return Err(Error::NoCachedVersions(resource.to_string()));
+            }
         }
 
-        // No up-to-date version cached, so we have to try downloading it.
-        let meta = self.try_download_resource(resource, &url, &path, &etag)?;
+        let mut builder = tar::Builder::new(writer);
+        for meta in &entries {
+            let relative_path = meta.resource_path.strip_prefix(&self.dir).map_err(|_| {
+                Error::CacheCorrupted(format!(
+                    "{:?} is outside of the cache directory",
+                    meta.resource_path
+                ))
+            })?;
+            debug!("Exporting {} from {:?}", meta.resource, meta.resource_path);
+            builder.append_path_with_name(&meta.resource_path, relative_path)?;
+            builder.append_path_with_name(&meta.meta_path, Meta::meta_path_for(relative_path))?;
+        }
+        builder.into_inner()?.flush()?;
 
-        info!("New version of {} cached", resource);
+        Ok(entries)
+    }
 
-        filelock.unlock()?;
-        debug!("Lock released for {}", resource);
+    /// Merge a bundle that was created with [`export()`](Cache::export) into this cache.
+    ///
+    /// Every resource in the bundle is validated against its meta before it's added to
+    /// the cache, and the paths in the meta are rewritten to point into this cache.
+    /// Versions that are already cached are left alone. An
+    /// [`InvalidBundle`](Error::InvalidBundle) or [`ChecksumMismatch`](Error::ChecksumMismatch)
+    /// error is returned if the bundle doesn't pass validation, in which case resources
+    /// that were validated before the failure are still imported.
+    ///
+    /// Returns the [`Meta`] of every version that was imported.
+    pub fn import<R: Read>(&self, reader: R) -> Result<Vec<Meta>, Error> {
+        // Unpack to a temp directory within the cache first so that files can be
+        // moved into place atomically. Its name marks it as a staging directory, so that
+        // the half-imported resources in it aren't treated as entries of the cache.
+        let temp_dir = tempfile::Builder::new()
+            .prefix(IMPORT_DIR_PREFIX)
+            .tempdir_in(&self.dir)?;
+        let policy = ExtractionPolicy::default();
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            policy.check_path(&path)?;
+            let entry_type = entry.header().entry_type();
+            if !entry_type.is_file() && !entry_type.is_dir() {
+                return Err(Error::InvalidBundle(format!(
+                    "entry {:?} isn't a regular file",
+                    path
+                )));
+            }
+            entry.unpack_in(temp_dir.path())?;
+        }
 
-        Ok(meta)
+        let mut meta_paths: Vec<PathBuf> = vec![];
+        find_meta_files(temp_dir.path(), &mut meta_paths);
+        meta_paths.sort();
+
+        let mut imported: Vec<Meta> = vec![];
+        for meta_path in meta_paths {
+            let mut meta =
+                Meta::from_path(&meta_path).map_err(|err| Error::InvalidBundle(err.to_string()))?;
+            let bundled_path = meta.resource_path.clone();
+            let relative_path = bundled_path.strip_prefix(temp_dir.path()).unwrap();
+
+            // The file name has to match the resource, otherwise it would never be found.
+            let resource_hash = hash_str(&meta.resource);
+            let file_name = relative_path.file_name().unwrap().to_string_lossy();
+            if file_name != resource_hash && !file_name.starts_with(&format!("{}.", resource_hash))
+            {
+                return Err(Error::InvalidBundle(format!(
+                    "{:?} doesn't match resource {}",
+                    relative_path, meta.resource
+                )));
+            }
+            self.verify(&meta).map_err(|err| match err {
+                Error::CacheCorrupted(msg) => Error::InvalidBundle(msg),
+                err => err,
+            })?;
+
+            let resource_path = self.dir.join(relative_path);
+            fs::create_dir_all(resource_path.parent().unwrap())?;
+            let filelock = lock(&resource_path)?;
+            if resource_path.is_file() && Meta::meta_path_for(&resource_path).is_file() {
+                debug!("{} is already cached at {:?}", meta.resource, resource_path);
+            } else {
+                info!("Importing {} to {:?}", meta.resource, resource_path);
+                fs::rename(&bundled_path, &resource_path)?;
+                meta.meta_path = Meta::meta_path_for(&resource_path);
+                meta.resource_path = resource_path;
+                meta.to_file()?;
+                imported.push(meta);
+            }
+            filelock.unlock()?;
+        }
+
+        Ok(imported)
     }
 
     /// Find existing versions of a cached resource, sorted by most recent first.
-    fn find_existing(&self, resource: &str, subdir: Option<&str>) -> Vec<Meta> {
+    pub(crate) fn find_existing(&self, resource: &str, subdir: Option<&str>) -> Vec<Meta> {
         let mut existing_meta: Vec<Meta> = vec![];
         let glob_string = format!(
             "{}.*.meta",
@@ -447,7 +1199,213 @@ impl Cache {
         existing_meta
     }
 
-    fn get_retry_delay(&self, retries: u32) -> u32 {
+    /// Make sure a cached version of a resource matches the expected checksum, if there is one.
+    ///
+    /// The cached file is only hashed if this checksum wasn't already verified when
+    /// the resource was cached.
+    pub(crate) fn verify_cached(
+        &self,
+        mut meta: Meta,
+        checksum: Option<&Checksum>,
+    ) -> Result<Meta, Error> {
+        if let Some(checksum) = checksum {
+            let expected = checksum.to_string();
+            if meta.checksum.as_deref() != Some(&expected[..]) {
+                debug!("Verifying checksum of cached version of {}", meta.resource);
+                let actual = checksum.compute_file(&meta.resource_path)?;
+                checksum.verify(&meta.resource, &actual)?;
+                meta.checksum = Some(expected);
+                meta.to_file()?;
+            }
+        }
+        Ok(meta)
+    }
+
+    /// Record an access to a cached resource so that the least-recently-used resources
+    /// can be evicted first. Access times only matter for eviction, so they're only
+    /// recorded when the cache has a [`max_size`](CacheBuilder::max_size).
+    ///
+    /// Returns `None` if the resource was evicted in the meantime.
+    pub(crate) fn record_access(&self, meta: Meta) -> Result<Option<Meta>, Error> {
+        if self.max_size.is_none() {
+            return Ok(Some(meta));
+        }
+        let filelock = lock(&meta.resource_path)?;
+        let meta = self.save_meta(meta, false);
+        filelock.unlock()?;
+        Ok(meta)
+    }
+
+    /// Write the meta of a cached resource if it was `updated`, recording an access to the
+    /// resource when the cache has a [`max_size`](CacheBuilder::max_size).
+    ///
+    /// Returns `None` without writing anything if the resource was evicted, so that no meta
+    /// is left behind for it. This should only be called while holding the lock on the
+    /// resource.
+    fn save_meta(&self, mut meta: Meta, updated: bool) -> Option<Meta> {
+        if !meta.resource_path.is_file() {
+            debug!("{} was evicted from the cache", meta.resource);
+            return None;
+        }
+        let result = if self.max_size.is_some() {
+            meta.touch()
+        } else if updated {
+            meta.to_file()
+        } else {
+            Ok(())
+        };
+        if let Err(err) = result {
+            warn!("Failed to update meta of {}: {}", meta.resource, err);
+        }
+        Some(meta)
+    }
+
+    /// Remove all but the newest `keep_versions` versions of a resource.
+    ///
+    /// This should only be called while holding the lock on the latest version, `latest`,
+    /// which is never removed.
+    pub(crate) fn remove_old_versions(&self, resource: &str, subdir: Option<&str>, latest: &Path) {
+        let keep_versions = match self.keep_versions {
+            Some(keep_versions) => std::cmp::max(keep_versions, 1),
+            None => return,
+        };
+        let versions = self.find_existing(resource, subdir);
+        for meta in versions.iter().skip(keep_versions) {
+            if meta.resource_path == latest {
+                continue;
+            }
+            match remove_entry(meta) {
+                Ok(true) => {
+                    info!(
+                        "Removed old version of {} at {:?}",
+                        resource, meta.resource_path
+                    );
+                }
+                Ok(false) => {
+                    debug!(
+                        "Skipping removal of old version of {} at {:?} since it's locked",
+                        resource, meta.resource_path
+                    );
+                }
+                Err(err) => {
+                    warn!("Failed to remove old version of {}: {}", resource, err);
+                }
+            }
+        }
+    }
+
+    /// Remove a cached version of a resource, along with its meta and extraction directories.
+    ///
+    /// Returns `false` without removing anything if the resource is in use by another
+    /// thread or process.
+    ///
+    /// ```rust,no_run
+    /// # use cached_path::Cache;
+    /// # let cache = Cache::new().unwrap();
+    /// for meta in cache.entries().filter(|meta| meta.resource().ends_with(".zip")) {
+    ///     cache.remove(&meta).unwrap();
+    /// }
+    /// ```
+    pub fn remove(&self, meta: &Meta) -> Result<bool, Error> {
+        remove_entry(meta)
+    }
+
+    /// Check that a cached version of a resource is intact.
+    ///
+    /// The size of the cached file is compared to the size that was recorded when it was
+    /// cached, and if a checksum was verified back then, the file is hashed again and
+    /// compared to that checksum. A [`CacheCorrupted`](Error::CacheCorrupted) or
+    /// [`ChecksumMismatch`](Error::ChecksumMismatch) error is returned if the file doesn't match.
+    pub fn verify(&self, meta: &Meta) -> Result<(), Error> {
+        let size = match fs::metadata(&meta.resource_path) {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => {
+                return Err(Error::CacheCorrupted(format!(
+                    "cached file for {} is missing",
+                    meta.resource
+                )));
+            }
+        };
+        if let Some(expected_size) = meta.size {
+            if size != expected_size {
+                return Err(Error::CacheCorrupted(format!(
+                    "cached file for {} has {} bytes, expected {}",
+                    meta.resource, size, expected_size
+                )));
+            }
+        }
+        if let Some(checksum) = &meta.checksum {
+            let checksum = Checksum::parse(checksum)?;
+            let actual = checksum.compute_file(&meta.resource_path)?;
+            checksum.verify(&meta.resource, &actual)?;
+        }
+        Ok(())
+    }
+
+    /// Evict the least-recently-used resources until the cache is under `max_size` bytes.
+    ///
+    /// Resources that are in use by another thread or process are skipped, and so are the
+    /// extraction directories of local archives, which aren't tracked by the cache.
+    /// Returns the [`Meta`] of every resource that was evicted.
+    pub fn evict_to(&self, max_size: u64) -> Vec<Meta> {
+        self.evict_until(max_size, None)
+    }
+
+    /// Evict the least-recently-used resources until the cache is under the
+    /// [`max_size`](CacheBuilder::max_size), if there is one.
+    ///
+    /// The resource at `in_use` is never evicted, nor are any resources that are
+    /// locked by another process.
+    pub(crate) fn evict(&self, in_use: &Path) {
+        if let Some(max_size) = self.max_size {
+            self.evict_until(max_size, Some(in_use));
+        }
+    }
+
+    fn evict_until(&self, max_size: u64, in_use: Option<&Path>) -> Vec<Meta> {
+        let mut evicted: Vec<Meta> = vec![];
+        let mut entries: Vec<(Meta, u64)> = self
+            .entries()
+            .map(|meta| {
+                let size = meta.disk_usage();
+                (meta, size)
+            })
+            .collect();
+        let mut total_size: u64 = entries.iter().map(|(_, size)| size).sum();
+        if total_size <= max_size {
+            return evicted;
+        }
+
+        debug!(
+            "Cache size of {} bytes exceeds maximum of {} bytes, evicting resources",
+            total_size, max_size
+        );
+        entries.sort_by(|(a, _), (b, _)| a.last_access().partial_cmp(&b.last_access()).unwrap());
+        for (meta, size) in entries {
+            if total_size <= max_size {
+                break;
+            }
+            if Some(meta.resource_path.as_path()) == in_use {
+                continue;
+            }
+            match remove_entry(&meta) {
+                Ok(true) => {
+                    info!("Evicted {} from cache", meta.resource);
+                    total_size = total_size.saturating_sub(size);
+                    evicted.push(meta);
+                }
+                Ok(false) => {
+                    debug!("Skipping eviction of {} since it's locked", meta.resource);
+                }
+                Err(err) => {
+                    warn!("Failed to evict {} from cache: {}", meta.resource, err);
+                }
+            }
+        }
+        evicted
+    }
+
+    pub(crate) fn get_retry_delay(&self, retries: u32) -> u32 {
         let between = Uniform::from(0..1000);
         let mut rng = rand::thread_rng();
         std::cmp::min(
@@ -456,152 +1414,234 @@ impl Cache {
         )
     }
 
-    fn try_download_resource(
+    /// Decide whether to retry a request for a resource that failed with `err`, where
+    /// `retries` is the number of the retry, returning how long to wait before retrying.
+    pub(crate) fn retry_delay(
         &self,
         resource: &str,
-        url: &reqwest::Url,
-        path: &Path,
-        etag: &Option<String>,
-    ) -> Result<Meta, Error> {
-        let mut retries: u32 = 0;
-        loop {
-            match self.download_resource(resource, url, path, etag) {
-                Ok(meta) => {
-                    return Ok(meta);
-                }
-                Err(err) => {
-                    if retries >= self.max_retries {
-                        error!("Max retries exceeded for {}", resource);
-                        return Err(err);
-                    }
-                    if !err.is_retriable() {
-                        error!("Download failed for {} with fatal error, {}", resource, err);
-                        return Err(err);
-                    }
-                    retries += 1;
-                    let retry_delay = self.get_retry_delay(retries);
-                    warn!(
-                        "Download failed for {}: {}\nRetrying in {} milliseconds...",
-                        resource, err, retry_delay
-                    );
-                    thread::sleep(time::Duration::from_millis(u64::from(retry_delay)));
-                }
-            }
+        retries: u32,
+        err: &Error,
+    ) -> Option<Duration> {
+        if retries > self.max_retries {
+            error!("Max retries exceeded for {}", resource);
+            return None;
+        }
+        if !err.is_retriable() {
+            error!("Request for {} failed with fatal error, {}", resource, err);
+            return None;
         }
+        let retry_delay = self.get_retry_delay(retries);
+        warn!(
+            "Request for {} failed: {}\nRetrying in {} milliseconds...",
+            resource, err, retry_delay
+        );
+        Some(Duration::from_millis(u64::from(retry_delay)))
     }
 
-    fn download_resource(
+    /// Save the body of a resource to the cache at `path`.
+    ///
+    /// The `metadata` determines the version of the resource, which is recorded in the meta.
+    /// If `resume_from` is non-zero, the body is appended to the partial file,
+    /// as long as the body starts where the partial file ends.
+    pub(crate) fn save_body(
         &self,
         resource: &str,
-        url: &reqwest::Url,
+        mut body: RemoteBody,
         path: &Path,
-        etag: &Option<String>,
+        metadata: &RemoteMetadata,
+        checksum: Option<&Checksum>,
+        resume_from: u64,
     ) -> Result<Meta, Error> {
-        debug!("Attempting connection to {}", url);
-
-        let mut response = self
-            .http_client
-            .get(url.clone())
-            .send()?
-            .error_for_status()?;
-
-        debug!("Opened connection to {}", url);
-
-        // First we make a temporary file and download the contents of the resource into it.
-        // Otherwise if we wrote directly to the cache file and the download got
-        // interrupted we could be left with a corrupted cache file.
-        let tempfile = NamedTempFile::new_in(path.parent().unwrap())?;
-        let mut tempfile_write_handle = OpenOptions::new().write(true).open(tempfile.path())?;
-
-        info!("Starting download of {}", url);
-
-        let bytes = if let Some(progress_bar) = &self.progress_bar {
-            let mut download_wrapper = progress_bar.wrap_download(
-                resource,
-                response.content_length(),
-                tempfile_write_handle,
-            );
-            let bytes = response.copy_to(&mut download_wrapper)?;
-            download_wrapper.finish();
-            bytes
+        let partial_path = Cache::partial_path(path);
+        let resume_from = Cache::start_offset(resource, body.offset, resume_from)?;
+        let mut partial_file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume_from > 0)
+            .truncate(resume_from == 0)
+            .open(&partial_path)?;
+
+        info!("Starting download of {}", resource);
+
+        // If we have an expected checksum, we hash the contents as we go,
+        // starting with anything downloaded previously.
+        let hashing_writer =
+            Cache::hashing_writer(&mut partial_file, checksum, &partial_path, resume_from)?;
+        let (bytes, actual_checksum) = if let Some(mut hashing_writer) = hashing_writer {
+            let bytes = self.copy_body(resource, &mut body, &mut hashing_writer)?;
+            (bytes, Some(hashing_writer.finish()))
         } else {
-            response.copy_to(&mut tempfile_write_handle)?
+            let bytes = self.copy_body(resource, &mut body, &mut partial_file)?;
+            (bytes, None)
         };
+        let bytes = resume_from + bytes;
+        drop(partial_file);
 
         info!("Downloaded {} bytes", bytes);
+
+        self.finish_download(
+            resource,
+            path,
+            &metadata.headers,
+            checksum,
+            actual_checksum,
+            bytes,
+        )
+    }
+
+    /// Get the number of bytes of the partial file for `path` that a download can resume
+    /// from, which is `0` if the source doesn't give a validator to resume with.
+    pub(crate) fn resume_offset(path: &Path, headers: &CacheHeaders) -> u64 {
+        match headers.range_validator() {
+            Some(_) => fs::metadata(Cache::partial_path(path))
+                .map(|m| m.len())
+                .unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// Get the offset to write a body that starts at `offset` to the partial file at,
+    /// given that the download was resumed from `resume_from`.
+    ///
+    /// If the source couldn't resume the download it sends the whole resource,
+    /// in which case we start over.
+    pub(crate) fn start_offset(
+        resource: &str,
+        offset: u64,
+        resume_from: u64,
+    ) -> Result<u64, Error> {
+        if offset == resume_from {
+            Ok(offset)
+        } else if offset == 0 {
+            debug!("Source sent full contents of {}, starting over", resource);
+            Ok(0)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "body of {} starts at byte {} instead of {}",
+                    resource, offset, resume_from
+                ),
+            )
+            .into())
+        }
+    }
+
+    /// Wrap a writer to hash the contents of a download, if there's an expected checksum,
+    /// starting with the first `resume_from` bytes of the partial file.
+    pub(crate) fn hashing_writer<W: Write>(
+        writer: W,
+        checksum: Option<&Checksum>,
+        partial_path: &Path,
+        resume_from: u64,
+    ) -> Result<Option<HashingWriter<W>>, Error> {
+        let checksum = match checksum {
+            Some(checksum) => checksum,
+            None => return Ok(None),
+        };
+        let mut hashing_writer = HashingWriter::new(writer, checksum.algorithm);
+        if resume_from > 0 {
+            hashing_writer.update_from(&mut File::open(partial_path)?.take(resume_from))?;
+        }
+        Ok(Some(hashing_writer))
+    }
+
+    /// Verify a finished download in the partial file for `path`, then write
+    /// its meta and move it into place.
+    pub(crate) fn finish_download(
+        &self,
+        resource: &str,
+        path: &Path,
+        headers: &CacheHeaders,
+        checksum: Option<&Checksum>,
+        actual_checksum: Option<Checksum>,
+        bytes: u64,
+    ) -> Result<Meta, Error> {
+        let partial_path = Cache::partial_path(path);
+
+        if let (Some(expected), Some(actual)) = (checksum, &actual_checksum) {
+            debug!("Verifying checksum of {}", resource);
+            if let Err(err) = expected.verify(resource, actual) {
+                // No sense in resuming from a corrupted file.
+                fs::remove_file(&partial_path)?;
+                return Err(err);
+            }
+        }
+
         debug!("Writing meta file");
 
-        let meta = Meta::new(
+        let mut meta = Meta::new(
             String::from(resource),
             path.into(),
-            etag.clone(),
+            headers.etag.clone(),
             self.freshness_lifetime,
+            Some(bytes),
         );
+        meta.checksum = actual_checksum.map(|checksum| checksum.to_string());
+        meta.update_from_headers(headers);
         meta.to_file()?;
 
-        debug!("Renaming temp file to cache location for {}", url);
+        debug!("Renaming partial file to cache location for {}", resource);
 
-        fs::rename(tempfile.path(), path)?;
+        fs::rename(&partial_path, path)?;
 
         Ok(meta)
     }
 
-    fn try_get_etag(&self, resource: &str, url: &reqwest::Url) -> Result<Option<String>, Error> {
-        let mut retries: u32 = 0;
-        loop {
-            match self.get_etag(url) {
-                Ok(etag) => return Ok(etag),
-                Err(err) => {
-                    if retries >= self.max_retries {
-                        error!("Max retries exceeded for {}", resource);
-                        return Err(err);
-                    }
-                    if !err.is_retriable() {
-                        error!("ETAG fetch for {} failed with fatal error", resource);
-                        return Err(err);
-                    }
-                    retries += 1;
-                    let retry_delay = self.get_retry_delay(retries);
-                    warn!(
-                        "ETAG fetch failed for {}, retrying in {} milliseconds...",
-                        resource, retry_delay
-                    );
-                    thread::sleep(time::Duration::from_millis(u64::from(retry_delay)));
-                }
-            }
-        }
+    /// Get the path of the partial file used while downloading to `path`.
+    pub(crate) fn partial_path(path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}.part", path.to_str().unwrap()))
     }
 
-    fn get_etag(&self, url: &reqwest::Url) -> Result<Option<String>, Error> {
-        debug!("Fetching ETAG for {}", url);
-        let response = self
-            .http_client
-            .head(url.clone())
-            .send()?
-            .error_for_status()?;
-        if let Some(etag) = response.headers().get(ETAG) {
-            if let Ok(s) = etag.to_str() {
-                Ok(Some(s.into()))
-            } else {
-                debug!("No ETAG for {}", url);
-                Ok(None)
+    fn copy_body<W: Write>(
+        &self,
+        resource: &str,
+        body: &mut RemoteBody,
+        writer: W,
+    ) -> Result<u64, Error> {
+        let mut download_wrapper = self.wrap_download(resource, body.size, writer);
+        match io::copy(&mut body.reader, &mut download_wrapper) {
+            Ok(bytes) => {
+                download_wrapper.finish();
+                Ok(bytes)
+            }
+            Err(err) => {
+                let err = Error::from(err);
+                download_wrapper.error(&err);
+                Err(err)
             }
-        } else {
-            Ok(None)
         }
     }
 
-    fn resource_to_filepath(
+    /// Wrap a writer to report the progress of a download.
+    pub(crate) fn wrap_download<W: Write>(
         &self,
         resource: &str,
-        etag: &Option<String>,
+        content_length: Option<u64>,
+        writer: W,
+    ) -> DownloadWrapper<W> {
+        wrap_download(
+            self.progress_reporter.as_ref(),
+            self.progress_bar.as_ref(),
+            resource,
+            content_length,
+            writer,
+        )
+    }
+
+    /// Get the cache path for a resource, where `version` is the ETAG of the resource or
+    /// some other identifier of the version of the resource.
+    pub(crate) fn resource_to_filepath(
+        &self,
+        resource: &str,
+        version: &Option<String>,
         subdir: Option<&str>,
         suffix: Option<&str>,
     ) -> PathBuf {
         let resource_hash = hash_str(resource);
-        let mut filename = if let Some(tag) = etag {
-            let etag_hash = hash_str(&tag[..]);
-            format!("{}.{}", resource_hash, etag_hash)
+        let mut filename = if let Some(version) = version {
+            let version_hash = hash_str(&version[..]);
+            format!("{}.{}", resource_hash, version_hash)
         } else {
             resource_hash
         };
@@ -620,11 +1660,208 @@ impl Cache {
     }
 }
 
+/// Split a resource like `https://example.com/bundle.tar.gz!/config/model.json` into the
+/// archive and the path of the member within it.
+///
+/// Local paths can have `!/` in them too, like `/data/a!/b.txt`, so they're only split if
+/// the part before the `!/` is a file. URLs are always split, and it's up to the caller to
+/// fetch the whole URL if the part before the `!/` turns out not to be an archive.
+pub(crate) fn split_archive_member(resource: &str) -> Option<(&str, &str)> {
+    let (archive, member) = resource.split_once("!/")?;
+    match Resource::parse(archive) {
+        Ok(Resource::Local(path)) if !path.is_file() => None,
+        _ => Some((archive, member)),
+    }
+}
+
+/// Check if a resource is a URL, rather than a local path or a `file://` URL.
+pub(crate) fn is_url(resource: &str) -> bool {
+    matches!(Resource::parse(resource), Ok(Resource::Url(_)))
+}
+
+/// Get the path to a member of an archive that was extracted to `dirpath`.
+pub(crate) fn archive_member_path(
+    resource: &str,
+    dirpath: &Path,
+    member: &str,
+) -> Result<PathBuf, Error> {
+    // Members can't point outside of the extraction directory.
+    let path = Path::new(member);
+    if ExtractionPolicy::default().check_path(path).is_ok() {
+        let path = dirpath.join(path);
+        if path.exists() {
+            return Ok(path);
+        }
+    }
+    Err(Error::ResourceNotFound(resource.into()))
+}
+
+/// Acquire an exclusive lock on the lock file for `path`, blocking until it's available.
+pub(crate) fn lock(path: &Path) -> Result<File, Error> {
+    let lock_path = format!("{}.lock", path.to_str().unwrap());
+    let filelock = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path)?;
+    filelock.lock_exclusive()?;
+    Ok(filelock)
+}
+
+/// Try to acquire an exclusive lock on the given lock file without blocking.
+///
+/// Returns `None` if the lock is currently held by someone else.
+fn try_lock(lock_path: &Path) -> Result<Option<fs::File>, Error> {
+    let filelock = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path)?;
+    match filelock.try_lock_exclusive() {
+        Ok(()) => Ok(Some(filelock)),
+        Err(err) if err.kind() == fs2::lock_contended_error().kind() => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Remove a cached resource along with its meta and extraction directories.
+///
+/// Returns `false` without removing anything if the resource or any of its extraction
+/// directories are locked.
+///
+/// The lock files are left in place. Otherwise a process that's waiting on the lock of
+/// the removed file would acquire it at the same time as a process that creates a new
+/// lock file at the same path.
+fn remove_entry(meta: &Meta) -> Result<bool, Error> {
+    let lock_path = PathBuf::from(format!("{}.lock", meta.resource_path.to_str().unwrap()));
+    let extraction_paths = meta.extraction_paths();
+
+    let filelock = match try_lock(&lock_path)? {
+        Some(filelock) => filelock,
+        None => return Ok(false),
+    };
+    let mut extraction_filelocks = vec![];
+    for extraction_path in &extraction_paths {
+        let extraction_lock_path =
+            PathBuf::from(format!("{}.lock", extraction_path.to_str().unwrap()));
+        if extraction_lock_path.exists() {
+            match try_lock(&extraction_lock_path)? {
+                Some(filelock) => extraction_filelocks.push(filelock),
+                None => return Ok(false),
+            }
+        }
+    }
+
+    for extraction_path in &extraction_paths {
+        if extraction_path.is_dir() {
+            fs::remove_dir_all(extraction_path)?;
+        } else if extraction_path.is_file() {
+            fs::remove_file(extraction_path)?;
+        }
+    }
+    if meta.resource_path.is_file() {
+        fs::remove_file(&meta.resource_path)?;
+    }
+    if meta.meta_path.is_file() {
+        fs::remove_file(&meta.meta_path)?;
+    }
+    let partial_path = Cache::partial_path(&meta.resource_path);
+    if partial_path.is_file() {
+        fs::remove_file(&partial_path)?;
+    }
+
+    drop(extraction_filelocks);
+    drop(filelock);
+
+    Ok(true)
+}
+
+/// The prefix of the directories that [`Cache::import()`] unpacks bundles into.
+const IMPORT_DIR_PREFIX: &str = ".import-";
+
+/// Recursively collect the paths of all meta files under `dir`, skipping over
+/// extraction directories and the staging directories of imports.
+fn find_meta_files(dir: &Path, meta_paths: &mut Vec<PathBuf>) {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(err) => {
+            debug!("Unable to read directory {:?}: {}", dir, err);
+            return;
+        }
+    };
+    for entry in read_dir.filter_map(Result::ok) {
+        let path = entry.path();
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        if path.is_dir() {
+            if !is_extraction_dir_name(&file_name) && !file_name.starts_with(IMPORT_DIR_PREFIX) {
+                find_meta_files(&path, meta_paths);
+            }
+        } else if file_name.ends_with(".meta") {
+            meta_paths.push(path);
+        }
+    }
+}
+
+/// Check if `file_name` is the name of an extraction directory, which is the name of the
+/// cache file of the archive (`<hash>` or `<hash>.<hash>`), followed by `-extracted` or
+/// `-extracted-<hash of the extract_only patterns>`.
+fn is_extraction_dir_name(file_name: &str) -> bool {
+    let is_hash = |s: &str, len: usize| {
+        s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let (cache_file_name, suffix) = match file_name.split_once("-extracted") {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let parts: Vec<&str> = cache_file_name.split('.').collect();
+    let suffix_matches = match suffix.strip_prefix('-') {
+        Some(patterns_hash) => is_hash(patterns_hash, 16),
+        None => suffix.is_empty(),
+    };
+    suffix_matches && parts.len() <= 2 && parts.iter().all(|part| is_hash(part, 64))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_find_meta_files_skips_extraction_and_import_dirs() {
+        let cache_dir = tempdir().unwrap();
+        let cache = Cache::builder()
+            .dir(cache_dir.path().to_owned())
+            .build()
+            .unwrap();
+
+        let path =
+            cache.resource_to_filepath("http://localhost:5000/foo.tar.gz", &None, None, None);
+        let version = Some("fake-etag".to_string());
+        let versioned_path =
+            cache.resource_to_filepath("http://localhost:5000/foo.tar.gz", &version, None, None);
+        let subdir = cache_dir.path().join("data-extracted-2024");
+        for dir in &[
+            PathBuf::from(format!("{}-extracted", path.to_str().unwrap())),
+            PathBuf::from(format!("{}-extracted", versioned_path.to_str().unwrap())),
+            PathBuf::from(format!(
+                "{}-extracted-0123456789abcdef",
+                versioned_path.to_str().unwrap()
+            )),
+            cache_dir.path().join(".import-a1b2c3"),
+            subdir.clone(),
+        ] {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("bar.meta"), "{}").unwrap();
+        }
+
+        let mut meta_paths = vec![];
+        find_meta_files(cache_dir.path(), &mut meta_paths);
+        assert_eq!(meta_paths, vec![subdir.join("bar.meta")]);
+    }
+
     #[test]
     fn test_url_to_filename_with_etag() {
         let cache_dir = tempdir().unwrap();
@@ -723,4 +1960,4 @@ mod tests {
             )
         );
     }
-}
\ No newline at end of file
+}
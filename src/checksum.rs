@@ -0,0 +1,210 @@
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::Error;
+
+/// Supported checksum algorithms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Algorithm {
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl Algorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// The length of the hex-encoded digest.
+    fn hex_len(&self) -> usize {
+        match self {
+            Algorithm::Sha256 => 64,
+            Algorithm::Sha512 => 128,
+            Algorithm::Blake3 => 64,
+        }
+    }
+}
+
+/// An expected checksum of a resource, in the form `<algorithm>:<hex digest>`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Checksum {
+    pub(crate) algorithm: Algorithm,
+    pub(crate) digest: String,
+}
+
+impl Checksum {
+    /// Parse a checksum from a string like `sha256:<hex digest>`.
+    pub(crate) fn parse(s: &str) -> Result<Self, Error> {
+        let (name, digest) = s
+            .split_once(':')
+            .ok_or_else(|| Error::InvalidChecksum(s.into()))?;
+        let algorithm = match name.to_lowercase().as_str() {
+            "sha256" => Algorithm::Sha256,
+            "sha512" => Algorithm::Sha512,
+            "blake3" => Algorithm::Blake3,
+            _ => return Err(Error::InvalidChecksum(s.into())),
+        };
+        let digest = digest.to_lowercase();
+        if digest.len() != algorithm.hex_len() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::InvalidChecksum(s.into()));
+        }
+        Ok(Self { algorithm, digest })
+    }
+
+    /// Compute the checksum of a file using the same algorithm as this checksum.
+    pub(crate) fn compute_file(&self, path: &Path) -> Result<Checksum, Error> {
+        let mut writer = HashingWriter::new(io::sink(), self.algorithm);
+        io::copy(&mut File::open(path)?, &mut writer)?;
+        Ok(writer.finish())
+    }
+
+    /// Check that the given checksum matches this one, otherwise return a
+    /// [`ChecksumMismatch`](crate::Error::ChecksumMismatch) error.
+    pub(crate) fn verify(&self, resource: &str, actual: &Checksum) -> Result<(), Error> {
+        if self == actual {
+            Ok(())
+        } else {
+            Err(Error::ChecksumMismatch {
+                resource: resource.into(),
+                expected: self.to_string(),
+                actual: actual.to_string(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm.name(), self.digest)
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+}
+
+/// Wraps a writer, hashing everything written to it.
+pub(crate) struct HashingWriter<W: Write> {
+    hasher: Hasher,
+    writer: W,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub(crate) fn new(writer: W, algorithm: Algorithm) -> Self {
+        let hasher = match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        };
+        Self { hasher, writer }
+    }
+
+    /// Hash everything from a reader without writing it.
+    pub(crate) fn update_from<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut buf = [0; 8192];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                return Ok(());
+            }
+            self.update(&buf[..n]);
+        }
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        match &mut self.hasher {
+            Hasher::Sha256(hasher) => hasher.update(buf),
+            Hasher::Sha512(hasher) => hasher.update(buf),
+            Hasher::Blake3(hasher) => {
+                hasher.update(buf);
+            }
+        };
+    }
+
+    /// Get the checksum of everything that has been written.
+    pub(crate) fn finish(self) -> Checksum {
+        match self.hasher {
+            Hasher::Sha256(hasher) => Checksum {
+                algorithm: Algorithm::Sha256,
+                digest: format!("{:x}", hasher.finalize()),
+            },
+            Hasher::Sha512(hasher) => Checksum {
+                algorithm: Algorithm::Sha512,
+                digest: format!("{:x}", hasher.finalize()),
+            },
+            Hasher::Blake3(hasher) => Checksum {
+                algorithm: Algorithm::Blake3,
+                digest: hasher.finalize().to_hex().to_string(),
+            },
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let checksum = Checksum::parse(
+            "SHA256:C0535E4BE2B79FFD93291305436BF889314E4A3FAEC05ECFFCBB7DF31AD9E51A",
+        )
+        .unwrap();
+        assert_eq!(checksum.algorithm, Algorithm::Sha256);
+        assert_eq!(
+            checksum.to_string(),
+            "sha256:c0535e4be2b79ffd93291305436bf889314e4a3faec05ecffcbb7df31ad9e51a"
+        );
+
+        assert!(Checksum::parse("sha256").is_err());
+        assert!(Checksum::parse("md5:d41d8cd98f00b204e9800998ecf8427e").is_err());
+        assert!(Checksum::parse("sha512:abcd").is_err());
+        assert!(Checksum::parse(&format!("blake3:{}", "z".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn test_hashing_writer() {
+        let cases = [
+            (
+                Algorithm::Sha256,
+                "sha256:dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f",
+            ),
+            (
+                Algorithm::Sha512,
+                "sha512:374d794a95cdcfd8b35993185fef9ba368f160d8daf432d08ba9f1ed1e5abe6c\
+                 c69291e0fa2fe0006a52570ef18c19def4e617c33ce52ef0a6e5fbe318cb0387",
+            ),
+            (
+                Algorithm::Blake3,
+                "blake3:288a86a79f20a3d6dccdca7713beaed178798296bdfa7913fa2a62d9727bf8f8",
+            ),
+        ];
+        for (algorithm, expected) in cases.iter() {
+            let mut writer = HashingWriter::new(Vec::new(), *algorithm);
+            writer.write_all(b"Hello, World!").unwrap();
+            assert_eq!(&writer.finish().to_string(), expected);
+        }
+    }
+}
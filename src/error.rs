@@ -1,6 +1,7 @@
-
 use thiserror::Error;
 
+use crate::PolicyViolation;
+
 /// Errors that can occur during caching.
 #[derive(Error, Debug)]
 pub enum Error {
@@ -12,6 +13,11 @@ pub enum Error {
     #[error("Unable to parse resource URL ({0})")]
     InvalidUrl(String),
 
+    /// Arises when the resource is a URL with a scheme that no
+    /// [`RemoteSource`](crate::RemoteSource) is registered for.
+    #[error("No source is registered for the URL scheme ({0})")]
+    UnsupportedScheme(String),
+
     /// Arises when the cache is being used in offline mode, but it couldn't locate
     /// any cached versions of a remote resource.
     #[error("Offline mode is enabled but no cached versions of resouce exist ({0})")]
@@ -26,8 +32,40 @@ pub enum Error {
     CacheCorrupted(String),
 
     /// Arises when a resource is treated as archive, but the extraction process fails.
+    /// The [`ExtractionErrorKind`] says why, like which rule of the
+    /// [`ExtractionPolicy`](crate::ExtractionPolicy) the archive broke.
     #[error("Extracting archive failed ({0})")]
-    ExtractionError(String),
+    ExtractionError(#[from] ExtractionErrorKind),
+
+    /// Arises when a checksum given through [`Options::checksum`](crate::Options::checksum)
+    /// can't be parsed.
+    #[error("Invalid checksum ({0})")]
+    InvalidChecksum(String),
+
+    /// Arises when a bundle given to [`Cache::import`](crate::Cache::import) is invalid.
+    #[error("Invalid bundle ({0})")]
+    InvalidBundle(String),
+
+    /// Arises when a glob pattern given through
+    /// [`Options::extract_only`](crate::Options::extract_only) can't be parsed.
+    #[error("Invalid glob pattern ({0})")]
+    InvalidGlob(String),
+
+    /// Arises when the credentials for a [`RemoteSource`](crate::RemoteSource), like a
+    /// service account key, can't be read or used.
+    #[error("Invalid credentials ({0})")]
+    InvalidCredentials(String),
+
+    /// Arises when the checksum of a resource doesn't match the expected checksum.
+    #[error("Checksum mismatch for {resource} (expected {expected}, got {actual})")]
+    ChecksumMismatch {
+        /// The resource.
+        resource: String,
+        /// The expected checksum.
+        expected: String,
+        /// The actual checksum of the resource.
+        actual: String,
+    },
 
     /// Any IO error that could arise while attempting to cache a remote resource.
     #[error("An IO error occurred")]
@@ -38,19 +76,50 @@ pub enum Error {
     HttpError(#[from] reqwest::Error),
 }
 
+/// Why extracting an archive failed, see [`Error::ExtractionError`].
+#[derive(Error, Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ExtractionErrorKind {
+    /// The archive broke a rule of the [`ExtractionPolicy`](crate::ExtractionPolicy), like
+    /// containing an entry with a path outside of the extraction directory.
+    #[error(transparent)]
+    PolicyViolation(#[from] PolicyViolation),
+
+    /// The archive is invalid, or its format couldn't be determined or isn't supported.
+    #[error("{0}")]
+    InvalidArchive(String),
+}
+
+impl From<PolicyViolation> for Error {
+    fn from(violation: PolicyViolation) -> Self {
+        Error::ExtractionError(violation.into())
+    }
+}
+
 impl Error {
+    pub(crate) fn invalid_archive(message: impl Into<String>) -> Self {
+        Error::ExtractionError(ExtractionErrorKind::InvalidArchive(message.into()))
+    }
+
     pub(crate) fn is_retriable(&self) -> bool {
         match self {
-            Error::HttpError(source) => {
-                if source.is_status() {
-                    matches!(
-                        source.status().map(|status| status.as_u16()),
-                        Some(502) | Some(503) | Some(504)
-                    )
-                } else {
-                    source.is_timeout()
-                }
-            }
+            Error::HttpError(source) => is_retriable_http_error(source),
+            // Bodies from remote sources are streamed through `std::io::Read`, so errors
+            // while downloading them show up as I/O errors.
+            Error::IoError(source) => match source
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<reqwest::Error>())
+            {
+                Some(inner) => is_retriable_http_error(inner),
+                None => matches!(
+                    source.kind(),
+                    std::io::ErrorKind::TimedOut
+                        | std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::ConnectionAborted
+                        | std::io::ErrorKind::UnexpectedEof
+                        | std::io::ErrorKind::Interrupted
+                ),
+            },
             _ => false,
         }
     }
@@ -62,4 +131,16 @@ impl Error {
             None
         }
     }
-}
\ No newline at end of file
+}
+
+fn is_retriable_http_error(source: &reqwest::Error) -> bool {
+    if source.is_status() {
+        matches!(
+            source.status().map(|status| status.as_u16()),
+            Some(502) | Some(503) | Some(504)
+        )
+    } else {
+        // Downloads that get cut off can be resumed, so they're worth retrying.
+        source.is_timeout() || source.is_body()
+    }
+}
@@ -0,0 +1,401 @@
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use thiserror::Error;
+
+/// Limits and rules that archives have to satisfy to be extracted.
+///
+/// Archives are never allowed to contain entries with absolute paths or paths with `..`
+/// components. How links are treated is configured with [`links()`](ExtractionPolicy::links),
+/// and by default there are no limits on the size of an archive.
+///
+/// Set the policy through [`Options::extraction_policy()`](crate::Options::extraction_policy).
+/// When an archive breaks a rule, extraction is aborted with an
+/// [`ExtractionError`](crate::Error::ExtractionError) error whose
+/// [`ExtractionErrorKind::PolicyViolation`](crate::ExtractionErrorKind::PolicyViolation)
+/// says which rule was broken, and nothing is left in the cache.
+///
+/// ```rust
+/// use cached_path::{ExtractionPolicy, LinkPolicy, Options};
+///
+/// let options = Options::default().extract().extraction_policy(
+///     ExtractionPolicy::default()
+///         .links(LinkPolicy::Reject)
+///         .max_total_size(1024 * 1024 * 1024)
+///         .max_entries(10_000)
+///         .max_compression_ratio(100.0),
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractionPolicy {
+    /// How symbolic links and hard links in archives are treated.
+    pub links: LinkPolicy,
+    /// An optional maximum total size (in bytes) of the uncompressed contents.
+    pub max_total_size: Option<u64>,
+    /// An optional maximum number of entries.
+    pub max_entries: Option<usize>,
+    /// An optional maximum ratio between the uncompressed and the compressed size.
+    pub max_compression_ratio: Option<f64>,
+}
+
+impl ExtractionPolicy {
+    /// Set how symbolic links and hard links are treated.
+    pub fn links(mut self, links: LinkPolicy) -> Self {
+        self.links = links;
+        self
+    }
+
+    /// Set the maximum total size (in bytes) of the uncompressed contents.
+    ///
+    /// For tar archives and single compressed files this is the size of the decompressed
+    /// stream, and for zip archives it's the sum of the sizes of the entries.
+    pub fn max_total_size(mut self, max_total_size: u64) -> Self {
+        self.max_total_size = Some(max_total_size);
+        self
+    }
+
+    /// Set the maximum number of entries (files, directories, and links).
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Set the maximum ratio between the uncompressed and the compressed size.
+    ///
+    /// The ratio is checked continuously while extracting, so a decompression bomb is
+    /// stopped before it fills up the disk.
+    pub fn max_compression_ratio(mut self, max_compression_ratio: f64) -> Self {
+        self.max_compression_ratio = Some(max_compression_ratio);
+        self
+    }
+
+    /// Check that the path of an entry stays inside of the extraction directory.
+    pub(crate) fn check_path(&self, path: &Path) -> Result<(), PolicyViolation> {
+        for component in path.components() {
+            match component {
+                Component::Prefix(_) | Component::RootDir => {
+                    return Err(PolicyViolation::AbsolutePath(path.into()));
+                }
+                Component::ParentDir => {
+                    return Err(PolicyViolation::ParentDirectory(path.into()));
+                }
+                Component::CurDir | Component::Normal(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a link at `path` to `target`.
+    ///
+    /// The target of symbolic links is relative to the directory of the link, while the
+    /// target of hard links is relative to the root of the archive.
+    pub(crate) fn check_link(
+        &self,
+        path: &Path,
+        target: &Path,
+        hard_link: bool,
+    ) -> Result<(), PolicyViolation> {
+        if self.links == LinkPolicy::Reject {
+            return Err(PolicyViolation::Link(path.into()));
+        }
+        let resolved = if hard_link {
+            target.to_path_buf()
+        } else {
+            path.parent().unwrap_or_else(|| Path::new("")).join(target)
+        };
+        if is_contained(&resolved) {
+            Ok(())
+        } else {
+            Err(PolicyViolation::LinkOutsideTarget {
+                path: path.into(),
+                target: target.into(),
+            })
+        }
+    }
+
+    /// Check that the symbolic links that were extracted to `root` don't resolve to
+    /// anything outside of `root`.
+    ///
+    /// Links are checked one at a time while extracting, but a chain of links can
+    /// still escape, e.g. when one link points to a directory that contains another link.
+    pub(crate) fn check_extracted_links(&self, root: &Path) -> Result<(), crate::Error> {
+        let root = fs::canonicalize(root)?;
+        let mut dirs = vec![root.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    dirs.push(entry.path());
+                } else if file_type.is_symlink() {
+                    // Dangling links have already been checked when they were extracted.
+                    if let Ok(resolved) = fs::canonicalize(entry.path()) {
+                        if !resolved.starts_with(&root) {
+                            let path = entry.path();
+                            return Err(PolicyViolation::LinkOutsideTarget {
+                                target: fs::read_link(&path)?,
+                                path: path.strip_prefix(&root).unwrap_or(&path).into(),
+                            }
+                            .into());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that a relative path doesn't go above the directory it's relative to.
+fn is_contained(path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return false,
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+        }
+    }
+    true
+}
+
+/// How symbolic links and hard links in archives are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinkPolicy {
+    /// Refuse to extract archives that contain any links.
+    Reject,
+    /// Allow links as long as they point to something inside of the extraction directory.
+    #[default]
+    Contain,
+}
+
+/// The rule of an [`ExtractionPolicy`] that an archive broke.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum PolicyViolation {
+    /// An entry has an absolute path.
+    #[error("entry '{}' has an absolute path", .0.display())]
+    AbsolutePath(PathBuf),
+
+    /// The path of an entry contains a `..` component.
+    #[error("entry '{}' has a path containing '..'", .0.display())]
+    ParentDirectory(PathBuf),
+
+    /// An entry is a link, but links are rejected by the [`LinkPolicy`].
+    #[error("entry '{}' is a link", .0.display())]
+    Link(PathBuf),
+
+    /// An entry is a link that points outside of the extraction directory.
+    #[error("entry '{}' links to '{}', outside of the extraction directory", .path.display(), .target.display())]
+    LinkOutsideTarget {
+        /// The path of the link.
+        path: PathBuf,
+        /// The target of the link.
+        target: PathBuf,
+    },
+
+    /// The total uncompressed size exceeds the maximum.
+    #[error("uncompressed size exceeds the maximum of {0} bytes")]
+    TotalSize(u64),
+
+    /// The number of entries exceeds the maximum.
+    #[error("number of entries exceeds the maximum of {0}")]
+    EntryCount(usize),
+
+    /// The compression ratio exceeds the maximum.
+    #[error("compression ratio exceeds the maximum of {0}")]
+    CompressionRatio(f64),
+}
+
+/// Keeps track of the progress of an extraction, enforcing the limits of a policy.
+///
+/// Violations that are detected while reading are reported through an IO error, since that's
+/// the only thing that can pass through decoders and the tar crate. The violation itself is
+/// kept here so it can be recovered with [`into_error()`](Tracker::into_error).
+pub(crate) struct Tracker<'a> {
+    policy: &'a ExtractionPolicy,
+    compressed: Rc<Cell<u64>>,
+    uncompressed: Cell<u64>,
+    entries: Cell<usize>,
+    violation: RefCell<Option<PolicyViolation>>,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(policy: &'a ExtractionPolicy) -> Self {
+        Self {
+            policy,
+            compressed: Rc::new(Cell::new(0)),
+            uncompressed: Cell::new(0),
+            entries: Cell::new(0),
+            violation: RefCell::new(None),
+        }
+    }
+
+    /// Wrap the reader of the compressed data to count how much of it has been read.
+    pub(crate) fn compressed<R: Read>(&self, reader: R) -> CountingReader<R> {
+        CountingReader {
+            inner: reader,
+            count: self.compressed.clone(),
+        }
+    }
+
+    /// Wrap the reader of uncompressed data to enforce the size and ratio limits.
+    pub(crate) fn uncompressed<'t, R: Read>(&'t self, reader: R) -> LimitedReader<'t, 'a, R> {
+        LimitedReader {
+            inner: reader,
+            tracker: self,
+        }
+    }
+
+    pub(crate) fn add_entry(&self) -> Result<(), PolicyViolation> {
+        let entries = self.entries.get() + 1;
+        self.entries.set(entries);
+        match self.policy.max_entries {
+            Some(max_entries) if entries > max_entries => {
+                Err(PolicyViolation::EntryCount(max_entries))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn add_uncompressed(&self, n: u64) -> Result<(), PolicyViolation> {
+        let uncompressed = self.uncompressed.get() + n;
+        self.uncompressed.set(uncompressed);
+        if let Some(max_total_size) = self.policy.max_total_size {
+            if uncompressed > max_total_size {
+                return Err(PolicyViolation::TotalSize(max_total_size));
+            }
+        }
+        if let Some(max_ratio) = self.policy.max_compression_ratio {
+            let compressed = self.compressed.get().max(1);
+            if uncompressed as f64 / compressed as f64 > max_ratio {
+                return Err(PolicyViolation::CompressionRatio(max_ratio));
+            }
+        }
+        Ok(())
+    }
+
+    /// Turn an error from extracting into a policy violation, if that's what caused it.
+    pub(crate) fn into_error(self, error: crate::Error) -> crate::Error {
+        match self.violation.into_inner() {
+            Some(violation) => violation.into(),
+            None => error,
+        }
+    }
+}
+
+/// Counts the bytes read from the inner reader.
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Fails once the uncompressed data read through it breaks the limits of the policy.
+pub(crate) struct LimitedReader<'t, 'a, R> {
+    inner: R,
+    tracker: &'t Tracker<'a>,
+}
+
+impl<'t, 'a, R: Read> Read for LimitedReader<'t, 'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Err(violation) = self.tracker.add_uncompressed(n as u64) {
+            let error = io::Error::other(violation.to_string());
+            *self.tracker.violation.borrow_mut() = Some(violation);
+            return Err(error);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_path() {
+        let policy = ExtractionPolicy::default();
+        assert!(policy.check_path(Path::new("a/./b.txt")).is_ok());
+        assert_eq!(
+            policy.check_path(Path::new("/etc/passwd")),
+            Err(PolicyViolation::AbsolutePath("/etc/passwd".into()))
+        );
+        assert_eq!(
+            policy.check_path(Path::new("a/../../b.txt")),
+            Err(PolicyViolation::ParentDirectory("a/../../b.txt".into()))
+        );
+    }
+
+    #[test]
+    fn test_check_link() {
+        let policy = ExtractionPolicy::default();
+        assert!(policy
+            .check_link(Path::new("a/b/link"), Path::new("../c.txt"), false)
+            .is_ok());
+        assert!(policy
+            .check_link(Path::new("a/link"), Path::new("a/c.txt"), true)
+            .is_ok());
+        assert!(policy
+            .check_link(Path::new("a/link"), Path::new("../../c.txt"), false)
+            .is_err());
+        assert!(policy
+            .check_link(Path::new("link"), Path::new("../c.txt"), true)
+            .is_err());
+        assert!(policy
+            .check_link(Path::new("link"), Path::new("/etc/passwd"), false)
+            .is_err());
+
+        let policy = ExtractionPolicy::default().links(LinkPolicy::Reject);
+        assert_eq!(
+            policy.check_link(Path::new("link"), Path::new("c.txt"), false),
+            Err(PolicyViolation::Link("link".into()))
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let policy = ExtractionPolicy::default()
+            .max_total_size(100)
+            .max_entries(2)
+            .max_compression_ratio(10.0);
+        let tracker = Tracker::new(&policy);
+        assert!(tracker.add_entry().is_ok());
+        assert!(tracker.add_entry().is_ok());
+        assert_eq!(tracker.add_entry(), Err(PolicyViolation::EntryCount(2)));
+
+        let tracker = Tracker::new(&policy);
+        io::copy(&mut tracker.compressed(&[0u8; 10][..]), &mut io::sink()).unwrap();
+        assert!(tracker.add_uncompressed(100).is_ok());
+        assert_eq!(
+            tracker.add_uncompressed(1),
+            Err(PolicyViolation::TotalSize(100))
+        );
+
+        let tracker = Tracker::new(&policy);
+        io::copy(&mut tracker.compressed(&[0u8; 5][..]), &mut io::sink()).unwrap();
+        assert_eq!(
+            tracker.add_uncompressed(51),
+            Err(PolicyViolation::CompressionRatio(10.0))
+        );
+    }
+}
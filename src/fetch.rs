@@ -0,0 +1,394 @@
+use log::{debug, info};
+use std::fs::File;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+use crate::cache::{lock, Cache, CachedVersion};
+use crate::checksum::Checksum;
+use crate::headers::CacheHeaders;
+use crate::remote::{ConditionalFetch, RemoteBody, RemoteMetadata, RemoteSource};
+use crate::{meta::Meta, Error};
+
+/// The response to a conditional request, see [`FetchIo::fetch_if_changed()`].
+pub(crate) enum Fetched<B> {
+    /// The latest cached version is still current.
+    NotModified(RemoteMetadata),
+    /// The resource has changed, or there was no cached version.
+    Modified(RemoteMetadata, B),
+}
+
+/// The I/O that fetching a remote resource into the cache takes.
+///
+/// The steps of fetching a resource are implemented once by [`fetch_remote_resource()`],
+/// so [`Cache`] and `AsyncCache` only differ in how they do I/O. The futures of the
+/// blocking cache are always ready, so it runs them with [`block_on()`].
+pub(crate) trait FetchIo {
+    /// The stream of the contents of a resource.
+    type Body;
+
+    /// Run `f`, which only touches the local file system, like acquiring locks and reading
+    /// or writing meta files.
+    async fn local<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Cache) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static;
+
+    /// Wait before retrying a request.
+    async fn sleep(&self, duration: Duration);
+
+    /// See [`RemoteSource::metadata()`].
+    async fn metadata(&self, resource: &str) -> Result<RemoteMetadata, Error>;
+
+    /// See [`RemoteSource::fetch()`].
+    async fn fetch(
+        &self,
+        resource: &str,
+        metadata: &RemoteMetadata,
+        offset: u64,
+    ) -> Result<Self::Body, Error>;
+
+    /// See [`RemoteSource::fetch_if_changed()`].
+    async fn fetch_if_changed(
+        &self,
+        resource: &str,
+        latest: Option<&Meta>,
+    ) -> Result<Option<Fetched<Self::Body>>, Error>;
+
+    /// Save a body to the cache at `path`. See `Cache::save_body()`.
+    async fn save_body(
+        &self,
+        resource: &str,
+        body: Self::Body,
+        path: &Path,
+        metadata: &RemoteMetadata,
+        checksum: Option<&Checksum>,
+        resume_from: u64,
+    ) -> Result<Meta, Error>;
+}
+
+/// Fetch a remote resource into the cache, unless an up-to-date version is already cached,
+/// and return its meta.
+pub(crate) async fn fetch_remote_resource<I: FetchIo>(
+    cache: &Cache,
+    io: &I,
+    resource: &str,
+    subdir: Option<&str>,
+    checksum: Option<&Checksum>,
+) -> Result<Meta, Error> {
+    Fetch {
+        cache,
+        io,
+        resource,
+        subdir,
+        checksum,
+    }
+    .run()
+    .await
+}
+
+/// Run a future that never has to wait, like [`fetch_remote_resource()`] with the blocking
+/// I/O of [`SourceIo`], to completion.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!("blocking I/O is always ready"),
+    }
+}
+
+/// The blocking I/O of [`Cache`], which fetches resources through a [`RemoteSource`].
+pub(crate) struct SourceIo<'a> {
+    pub(crate) cache: &'a Cache,
+    pub(crate) source: &'a dyn RemoteSource,
+}
+
+impl FetchIo for SourceIo<'_> {
+    type Body = RemoteBody;
+
+    async fn local<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Cache) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        f(self.cache)
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    async fn metadata(&self, resource: &str) -> Result<RemoteMetadata, Error> {
+        self.source.metadata(resource)
+    }
+
+    async fn fetch(
+        &self,
+        resource: &str,
+        metadata: &RemoteMetadata,
+        offset: u64,
+    ) -> Result<RemoteBody, Error> {
+        self.source.fetch(resource, metadata, offset)
+    }
+
+    async fn fetch_if_changed(
+        &self,
+        resource: &str,
+        latest: Option<&Meta>,
+    ) -> Result<Option<Fetched<RemoteBody>>, Error> {
+        Ok(match self.source.fetch_if_changed(resource, latest)? {
+            None => None,
+            Some(ConditionalFetch::NotModified(metadata)) => Some(Fetched::NotModified(metadata)),
+            Some(ConditionalFetch::Modified(metadata, body)) => {
+                Some(Fetched::Modified(metadata, body))
+            }
+        })
+    }
+
+    async fn save_body(
+        &self,
+        resource: &str,
+        body: RemoteBody,
+        path: &Path,
+        metadata: &RemoteMetadata,
+        checksum: Option<&Checksum>,
+        resume_from: u64,
+    ) -> Result<Meta, Error> {
+        self.cache
+            .save_body(resource, body, path, metadata, checksum, resume_from)
+    }
+}
+
+/// A resource being fetched into the cache.
+struct Fetch<'a, I> {
+    cache: &'a Cache,
+    io: &'a I,
+    resource: &'a str,
+    subdir: Option<&'a str>,
+    checksum: Option<&'a Checksum>,
+}
+
+impl<I: FetchIo> Fetch<'_, I> {
+    async fn run(&self) -> Result<Meta, Error> {
+        let cached = self
+            .local(|cache, resource, subdir, checksum| {
+                cache.check_cached_versions(resource, subdir, checksum)
+            })
+            .await?;
+        let latest = match cached {
+            CachedVersion::Usable(meta) => return Ok(meta),
+            CachedVersion::Stale(latest) => latest,
+        };
+
+        if self.cache.conditional_get {
+            if let Some(meta) = self
+                .with_retries(|| self.fetch_conditionally(latest.as_ref()))
+                .await?
+            {
+                return Ok(meta);
+            }
+            debug!("{} doesn't support conditional requests", self.resource);
+        }
+
+        // No existing version or the existing versions are older than their freshness
+        // lifetimes, so we'll query for the ETAG of the resource and then compare
+        // that with any existing versions.
+        let metadata = self
+            .with_retries(|| self.io.metadata(self.resource))
+            .await?;
+        let path =
+            self.cache
+                .resource_to_filepath(self.resource, &metadata.version(), self.subdir, None);
+
+        // Before going further we need to obtain a lock on the file to provide
+        // parallel downloads of the same resource.
+        let (filelock, exists) = self.lock(&path).await?;
+
+        if exists {
+            // Oh cool! The cache is up-to-date according to the ETAG.
+            // We'll return the up-to-date version and clean up any other
+            // dangling ones.
+            info!("Cached version of {} is up-to-date", self.resource);
+            let meta = self
+                .up_to_date_version(&path, Some(&metadata.headers))
+                .await;
+            filelock.unlock()?;
+            return meta;
+        }
+
+        // No up-to-date version cached, so we have to try downloading it.
+        let meta = self
+            .with_retries(|| self.download_resource(&path, &metadata))
+            .await?;
+
+        self.finish(filelock, &path).await?;
+
+        Ok(meta)
+    }
+
+    /// Fetch the resource with a conditional request based on the `latest` cached version.
+    ///
+    /// Returns `None` if the source doesn't support conditional requests.
+    async fn fetch_conditionally(&self, latest: Option<&Meta>) -> Result<Option<Meta>, Error> {
+        let (metadata, body) = match self.io.fetch_if_changed(self.resource, latest).await? {
+            None => return Ok(None),
+            Some(Fetched::NotModified(metadata)) => {
+                let latest = latest.cloned();
+                return self
+                    .local(move |cache, resource, _, checksum| {
+                        cache.not_modified_version(
+                            resource,
+                            latest.as_ref(),
+                            &metadata.headers,
+                            checksum,
+                        )
+                    })
+                    .await
+                    .map(Some);
+            }
+            Some(Fetched::Modified(metadata, body)) => (metadata, body),
+        };
+
+        let path =
+            self.cache
+                .resource_to_filepath(self.resource, &metadata.version(), self.subdir, None);
+        let (filelock, exists) = self.lock(&path).await?;
+
+        if exists {
+            // This version was already cached, possibly by another process while we
+            // were waiting on the lock, or the source doesn't support conditional requests.
+            info!("Cached version of {} is up-to-date", self.resource);
+            drop(body);
+            let meta = self.up_to_date_version(&path, None).await;
+            filelock.unlock()?;
+            return meta.map(Some);
+        }
+
+        let meta = self
+            .io
+            .save_body(self.resource, body, &path, &metadata, self.checksum, 0)
+            .await?;
+
+        self.finish(filelock, &path).await?;
+
+        Ok(Some(meta))
+    }
+
+    async fn download_resource(
+        &self,
+        path: &Path,
+        metadata: &RemoteMetadata,
+    ) -> Result<Meta, Error> {
+        // We download the contents of the resource into a partial file first.
+        // Otherwise if we wrote directly to the cache file and the download got
+        // interrupted we could be left with a corrupted cache file.
+        // The partial file is kept around when a download fails, so that the next attempt
+        // can pick up where this one left off, as long as the resource hasn't changed
+        // according to its ETAG or last modified time.
+        let resume_from = {
+            let path = path.to_path_buf();
+            let headers = metadata.headers.clone();
+            self.io
+                .local(move |_| Ok(Cache::resume_offset(&path, &headers)))
+                .await?
+        };
+
+        let body = self.io.fetch(self.resource, metadata, resume_from).await?;
+
+        self.io
+            .save_body(
+                self.resource,
+                body,
+                path,
+                metadata,
+                self.checksum,
+                resume_from,
+            )
+            .await
+    }
+
+    /// Call `f` until it succeeds, retrying errors that are worth retrying with exponential
+    /// backoff, up to `max_retries` times.
+    async fn with_retries<T, F, Fut>(&self, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut retries: u32 = 0;
+        loop {
+            match f().await {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    retries += 1;
+                    match self.cache.retry_delay(self.resource, retries, &err) {
+                        Some(delay) => self.io.sleep(delay).await,
+                        None => return Err(err),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Acquire the lock for the cache file at `path`, and check if the file exists.
+    async fn lock(&self, path: &Path) -> Result<(File, bool), Error> {
+        debug!("Acquiring lock for cache of {}", self.resource);
+        let path = path.to_path_buf();
+        let locked = self
+            .io
+            .local(move |_| {
+                let filelock = lock(&path)?;
+                Ok((filelock, path.exists()))
+            })
+            .await?;
+        debug!("Lock acquired for {}", self.resource);
+        Ok(locked)
+    }
+
+    /// See [`Cache::up_to_date_version()`].
+    async fn up_to_date_version(
+        &self,
+        path: &Path,
+        headers: Option<&CacheHeaders>,
+    ) -> Result<Meta, Error> {
+        let path = path.to_path_buf();
+        let headers = headers.cloned();
+        self.local(move |cache, resource, subdir, checksum| {
+            cache.up_to_date_version(resource, subdir, &path, headers.as_ref(), checksum)
+        })
+        .await
+    }
+
+    /// Clean up after caching a new version of the resource at `path`, then release its lock.
+    async fn finish(&self, filelock: File, path: &Path) -> Result<(), Error> {
+        info!("New version of {} cached", self.resource);
+
+        let path: PathBuf = path.to_path_buf();
+        self.local(move |cache, resource, subdir, _| {
+            cache.clean_up(resource, subdir, &path);
+            Ok(())
+        })
+        .await?;
+
+        filelock.unlock()?;
+        debug!("Lock released for {}", self.resource);
+        Ok(())
+    }
+
+    /// Run `f` with [`FetchIo::local()`], passing it the resource, subdirectory, and checksum.
+    async fn local<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Cache, &str, Option<&str>, Option<&Checksum>) -> Result<T, Error>
+            + Send
+            + 'static,
+        T: Send + 'static,
+    {
+        let resource = self.resource.to_string();
+        let subdir = self.subdir.map(String::from);
+        let checksum = self.checksum.cloned();
+        self.io
+            .local(move |cache| f(cache, &resource, subdir.as_deref(), checksum.as_ref()))
+            .await
+    }
+}
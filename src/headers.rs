@@ -0,0 +1,275 @@
+use reqwest::header::{
+    HeaderMap, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, EXPIRES,
+    LAST_MODIFIED,
+};
+use std::time::SystemTime;
+
+use crate::utils::{now, uri_decode};
+
+/// Caching-related information from the headers of an HTTP response.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct CacheHeaders {
+    /// The `ETag` header.
+    pub(crate) etag: Option<String>,
+    /// The `Last-Modified` header.
+    pub(crate) last_modified: Option<String>,
+    /// The `Content-Length` header.
+    pub(crate) content_length: Option<u64>,
+    /// Time that the freshness of the response expires according to the `Cache-Control`
+    /// or `Expires` headers.
+    pub(crate) expires: Option<f64>,
+    /// Whether `Cache-Control` marks the response as immutable.
+    pub(crate) immutable: bool,
+    /// The `Content-Type` header.
+    pub(crate) content_type: Option<String>,
+    /// The file name from the `Content-Disposition` header.
+    pub(crate) filename: Option<String>,
+}
+
+impl CacheHeaders {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let etag = header_str(headers, ETAG);
+        let last_modified = header_str(headers, LAST_MODIFIED);
+        let content_length = header_str(headers, CONTENT_LENGTH).and_then(|s| s.parse().ok());
+        let content_type = header_str(headers, CONTENT_TYPE);
+        let filename =
+            header_str(headers, CONTENT_DISPOSITION).and_then(|value| parse_filename(&value));
+
+        let mut max_age: Option<u64> = None;
+        let mut no_cache = false;
+        let mut immutable = false;
+        for value in headers.get_all(CACHE_CONTROL) {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            for directive in value.split(',') {
+                let mut parts = directive.splitn(2, '=');
+                let name = parts.next().unwrap().trim().to_lowercase();
+                let arg = parts.next().map(|arg| arg.trim().trim_matches('"'));
+                match name.as_str() {
+                    // Either way the response has to be revalidated every time.
+                    "no-cache" | "no-store" => no_cache = true,
+                    "max-age" => max_age = arg.and_then(|arg| arg.parse().ok()),
+                    "immutable" => immutable = true,
+                    _ => {}
+                }
+            }
+        }
+
+        let expires = if no_cache {
+            immutable = false;
+            None
+        } else if let Some(max_age) = max_age {
+            // 'max-age' takes precedence over 'Expires'.
+            Some(now() + max_age as f64)
+        } else {
+            header_str(headers, EXPIRES)
+                .and_then(|expires| httpdate::parse_http_date(&expires).ok())
+                .and_then(|expires| expires.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|expires| expires.as_secs_f64())
+        };
+
+        Self {
+            etag,
+            last_modified,
+            content_length,
+            expires,
+            immutable,
+            content_type,
+            filename,
+        }
+    }
+
+    /// An identifier for the version of the resource.
+    ///
+    /// This is the ETAG if there is one. Otherwise the `Last-Modified` and `Content-Length`
+    /// headers are used as a weaker validator, if the server sent either of them.
+    pub(crate) fn version(&self) -> Option<String> {
+        if let Some(etag) = &self.etag {
+            Some(etag.clone())
+        } else if self.last_modified.is_some() || self.content_length.is_some() {
+            Some(format!(
+                "{};{}",
+                self.last_modified.as_deref().unwrap_or_default(),
+                self.content_length
+                    .map(|length| length.to_string())
+                    .unwrap_or_default(),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// A validator to use with the `If-Range` header, if there is one.
+    pub(crate) fn range_validator(&self) -> Option<&str> {
+        self.etag.as_deref().or(self.last_modified.as_deref())
+    }
+}
+
+/// Get the file name from the value of a `Content-Disposition` header, preferring
+/// the extended `filename*` parameter over `filename`.
+fn parse_filename(content_disposition: &str) -> Option<String> {
+    let mut filename: Option<String> = None;
+    for param in content_disposition.split(';') {
+        let mut parts = param.splitn(2, '=');
+        let name = parts.next().unwrap().trim().to_lowercase();
+        let value = match parts.next() {
+            Some(value) => value.trim(),
+            None => continue,
+        };
+        match name.as_str() {
+            // The value looks like "UTF-8''model%20v2.tar.gz".
+            "filename*" => {
+                if let Some((_, value)) = value.split_once("''") {
+                    let value = uri_decode(value);
+                    if !value.is_empty() {
+                        return Some(value);
+                    }
+                }
+            }
+            "filename" => filename = Some(value.trim_matches('"').into()),
+            _ => {}
+        }
+    }
+    filename.filter(|filename| !filename.is_empty())
+}
+
+fn header_str(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn parse(headers: &[(reqwest::header::HeaderName, &'static str)]) -> CacheHeaders {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.append(name, HeaderValue::from_static(value));
+        }
+        CacheHeaders::from_headers(&header_map)
+    }
+
+    #[test]
+    fn test_no_headers() {
+        assert_eq!(parse(&[]), CacheHeaders::default());
+    }
+
+    #[test]
+    fn test_max_age() {
+        let headers = parse(&[
+            (CACHE_CONTROL, "public, max-age=300"),
+            (EXPIRES, "Wed, 21 Oct 2015 07:28:00 GMT"),
+        ]);
+        let expires = headers.expires.unwrap();
+        assert!(expires > now() + 290.0 && expires <= now() + 300.0);
+        assert!(!headers.immutable);
+    }
+
+    #[test]
+    fn test_immutable() {
+        let headers = parse(&[(CACHE_CONTROL, "max-age=31536000, immutable")]);
+        assert!(headers.expires.is_some());
+        assert!(headers.immutable);
+    }
+
+    #[test]
+    fn test_no_cache() {
+        let headers = parse(&[
+            (CACHE_CONTROL, "max-age=300, immutable"),
+            (CACHE_CONTROL, "no-cache"),
+        ]);
+        assert_eq!(headers.expires, None);
+        assert!(!headers.immutable);
+
+        let headers = parse(&[(CACHE_CONTROL, "no-store")]);
+        assert_eq!(headers.expires, None);
+    }
+
+    #[test]
+    fn test_expires() {
+        let headers = parse(&[(EXPIRES, "Wed, 21 Oct 2015 07:28:00 GMT")]);
+        assert_eq!(headers.expires, Some(1_445_412_480.0));
+
+        // Invalid dates are ignored.
+        let headers = parse(&[(EXPIRES, "0")]);
+        assert_eq!(headers.expires, None);
+    }
+
+    #[test]
+    fn test_validators() {
+        let headers = parse(&[
+            (ETAG, "\"abc\""),
+            (LAST_MODIFIED, "Wed, 21 Oct 2015 07:28:00 GMT"),
+            (CONTENT_LENGTH, "14"),
+        ]);
+        assert_eq!(headers.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(
+            headers.last_modified.as_deref(),
+            Some("Wed, 21 Oct 2015 07:28:00 GMT")
+        );
+        assert_eq!(headers.content_length, Some(14));
+        assert_eq!(headers.version().as_deref(), Some("\"abc\""));
+        assert_eq!(headers.range_validator(), Some("\"abc\""));
+    }
+
+    #[test]
+    fn test_version_without_etag() {
+        let headers = parse(&[
+            (LAST_MODIFIED, "Wed, 21 Oct 2015 07:28:00 GMT"),
+            (CONTENT_LENGTH, "14"),
+        ]);
+        assert_eq!(
+            headers.version().as_deref(),
+            Some("Wed, 21 Oct 2015 07:28:00 GMT;14")
+        );
+        assert_eq!(
+            headers.range_validator(),
+            Some("Wed, 21 Oct 2015 07:28:00 GMT")
+        );
+
+        let headers = parse(&[(CONTENT_LENGTH, "14")]);
+        assert_eq!(headers.version().as_deref(), Some(";14"));
+        assert_eq!(headers.range_validator(), None);
+
+        assert_eq!(parse(&[]).version(), None);
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        let headers = parse(&[
+            (CONTENT_TYPE, "application/gzip"),
+            (CONTENT_DISPOSITION, "attachment; filename=\"model.tar.gz\""),
+        ]);
+        assert_eq!(headers.content_type.as_deref(), Some("application/gzip"));
+        assert_eq!(headers.filename.as_deref(), Some("model.tar.gz"));
+
+        let headers = parse(&[(
+            CONTENT_DISPOSITION,
+            "attachment; filename=\"model.tgz\"; filename*=UTF-8''model.tar.xz",
+        )]);
+        assert_eq!(headers.filename.as_deref(), Some("model.tar.xz"));
+
+        // Extended file names are percent-decoded.
+        let headers = parse(&[(
+            CONTENT_DISPOSITION,
+            "attachment; filename*=UTF-8''mod%C3%A8le%20v2.tar.gz",
+        )]);
+        assert_eq!(headers.filename.as_deref(), Some("modèle v2.tar.gz"));
+
+        // An empty extended file name falls back to the plain one.
+        let headers = parse(&[(
+            CONTENT_DISPOSITION,
+            "attachment; filename*=UTF-8''; filename=\"model.tgz\"",
+        )]);
+        assert_eq!(headers.filename.as_deref(), Some("model.tgz"));
+
+        let headers = parse(&[(CONTENT_DISPOSITION, "inline")]);
+        assert_eq!(headers.filename, None);
+    }
+}
@@ -0,0 +1,397 @@
+use log::{debug, info};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{AUTHORIZATION, LOCATION, RANGE};
+use reqwest::redirect::Policy;
+use reqwest::{Method, StatusCode, Url};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::headers::CacheHeaders;
+use crate::remote::{RemoteBody, RemoteMetadata, RemoteSource};
+use crate::utils::{env_var, home_dir, uri_encode};
+use crate::Error;
+
+/// The header with the commit hash that the revision of a file resolved to.
+const REPO_COMMIT: &str = "x-repo-commit";
+/// The header with the ETAG of an LFS file, since the response itself is a redirect.
+const LINKED_ETAG: &str = "x-linked-etag";
+/// The header with the size of an LFS file.
+const LINKED_SIZE: &str = "x-linked-size";
+
+/// The maximum number of redirects to follow.
+const MAX_REDIRECTS: usize = 10;
+
+/// The [`RemoteSource`] for files in repositories on the Hugging Face Hub, given as
+/// `hf://org/repo/path/to/file` or `hf://org/repo@revision/path/to/file`.
+///
+/// The revision is a branch, tag, or commit hash, and defaults to `main`. Revisions with
+/// slashes, like `refs/pr/1`, have to be percent-encoded. Files in dataset and space
+/// repositories are given as `hf://datasets/org/repo/path/to/file` and
+/// `hf://spaces/org/repo/path/to/file`.
+///
+/// A source with the default settings is registered for the `hf` scheme by
+/// [`CacheBuilder`](crate::CacheBuilder), with a client that has the timeouts and proxies
+/// set on the builder. Register your own with
+/// [`CacheBuilder::remote_source()`](crate::CacheBuilder::remote_source) to change them.
+///
+/// Unless a token is set with [`token()`](HfSource::token), requests are authenticated with
+/// the token in the `HF_TOKEN` environment variable, or else the token saved by
+/// `huggingface-cli login`, if there is one.
+///
+/// The version of a file is the hash of the commit that its revision resolved to, and the
+/// file is downloaded from that commit so that it can't change during the download. LFS
+/// files are versioned by the hash of their contents instead, from the `X-Linked-Etag`
+/// header, so they're only downloaded again when they change.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use cached_path::Cache;
+///
+/// let cache = Cache::new().unwrap();
+/// let path = cache
+///     .cached_path("hf://bert-base-uncased/bert-base-uncased@main/config.json")
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct HfSource {
+    client: Client,
+    endpoint: Option<String>,
+    token: Option<String>,
+}
+
+impl HfSource {
+    /// Create an `HfSource` with its own HTTP client.
+    pub fn new() -> Result<Self, Error> {
+        Self::with_client_builder(Client::builder())
+    }
+
+    /// Create an `HfSource` with an HTTP client from the given builder.
+    ///
+    /// Redirects are always followed by the source itself, since the headers of the
+    /// redirects for LFS files describe the file, so the redirect policy of the builder
+    /// is replaced.
+    pub fn with_client_builder(
+        client_builder: reqwest::blocking::ClientBuilder,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            client: client_builder.redirect(Policy::none()).build()?,
+            endpoint: None,
+            token: None,
+        })
+    }
+
+    /// Set the base URL of the hub, like `http://localhost:8080` for a local stand-in.
+    ///
+    /// This can also be set through the `HF_ENDPOINT` environment variable.
+    /// The default is `https://huggingface.co`.
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.trim_end_matches('/').into());
+        self
+    }
+
+    /// Set the token to authenticate requests with, instead of looking it up in the
+    /// environment.
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn resolve_endpoint(&self) -> String {
+        self.endpoint
+            .clone()
+            .or_else(|| env_var("HF_ENDPOINT").map(|e| e.trim_end_matches('/').to_string()))
+            .unwrap_or_else(|| "https://huggingface.co".into())
+    }
+
+    fn resolve_token(&self) -> Option<String> {
+        if let Some(token) = &self.token {
+            return Some(token.clone());
+        }
+        if let Some(token) = env_var("HF_TOKEN").or_else(|| env_var("HUGGING_FACE_HUB_TOKEN")) {
+            return Some(token);
+        }
+        let hf_home = env_var("HF_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".cache").join("huggingface")))?;
+        fs::read_to_string(hf_home.join("token"))
+            .ok()
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+    }
+
+    /// Get the URL of a file at the given revision.
+    fn file_url(&self, file: &HfFile, revision: &str) -> Result<Url, Error> {
+        let url = format!(
+            "{}/{}/resolve/{}/{}",
+            self.resolve_endpoint(),
+            file.repo,
+            uri_encode(revision, true),
+            uri_encode(&file.path, false),
+        );
+        Url::parse(&url).map_err(|_| Error::InvalidUrl(url))
+    }
+
+    /// Send a request, following redirects, unless `stop_at_redirect` returns true
+    /// for the redirect response.
+    ///
+    /// The token is only sent to the hub itself, not to the hosts it redirects to.
+    fn send(
+        &self,
+        method: Method,
+        url: Url,
+        headers: &[(&str, String)],
+        stop_at_redirect: impl Fn(&Response, &Url) -> bool,
+    ) -> Result<Response, Error> {
+        let token = self.resolve_token();
+        let hub = Url::parse(&self.resolve_endpoint()).ok();
+        let mut url = url;
+        for _ in 0..=MAX_REDIRECTS {
+            debug!("Sending {} request to {}", method, url);
+            let mut request = self.client.request(method.clone(), url.clone());
+            for (name, value) in headers {
+                request = request.header(*name, value);
+            }
+            if let (Some(token), Some(hub)) = (&token, &hub) {
+                if url.origin() == hub.origin() {
+                    request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+                }
+            }
+            let response = request.send()?;
+            if !response.status().is_redirection() {
+                return Ok(response);
+            }
+            let location = match response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| url.join(location).ok())
+            {
+                Some(location) => location,
+                None => return Ok(response),
+            };
+            if stop_at_redirect(&response, &location) {
+                return Ok(response);
+            }
+            url = location;
+        }
+        Err(Error::InvalidUrl(format!("too many redirects for {}", url)))
+    }
+}
+
+impl fmt::Debug for HfSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keep the token out of logs.
+        f.debug_struct("HfSource")
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+impl RemoteSource for HfSource {
+    fn metadata(&self, resource: &str) -> Result<RemoteMetadata, Error> {
+        let file = HfFile::parse(resource)?;
+        let url = self.file_url(&file, &file.revision)?;
+        // Redirects within the hub, like those for renamed repos, are followed, but
+        // the redirect to the storage for LFS files has the headers we need.
+        let response = self.send(Method::HEAD, url, &[], |response, _| {
+            response.headers().contains_key(REPO_COMMIT)
+        })?;
+        let response = response.error_for_status()?;
+
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let mut headers = CacheHeaders::from_headers(response.headers());
+        if response.status().is_redirection() {
+            // These describe the redirect, not the file.
+            headers.content_length = None;
+            headers.content_type = None;
+            headers.last_modified = None;
+        }
+        if let Some(size) = header(LINKED_SIZE).and_then(|size| size.parse().ok()) {
+            headers.content_length = Some(size);
+        }
+        // The file is downloaded from the commit that the revision resolved to. That commit
+        // is also the version of the file, except for LFS files, which are identified by
+        // the hash of their contents so that new commits that don't change them don't
+        // cause them to be downloaded again.
+        let commit = header(REPO_COMMIT);
+        if let Some(commit) = &commit {
+            debug!("{} resolved to commit {}", resource, commit);
+        }
+        if let Some(version) = header(LINKED_ETAG).or_else(|| commit.clone()) {
+            headers.etag = Some(version);
+        }
+        let mut metadata = RemoteMetadata::from_headers(headers);
+        metadata.revision = commit;
+        Ok(metadata)
+    }
+
+    fn fetch(
+        &self,
+        resource: &str,
+        metadata: &RemoteMetadata,
+        offset: u64,
+    ) -> Result<RemoteBody, Error> {
+        let file = HfFile::parse(resource)?;
+        let commit = metadata
+            .revision
+            .as_deref()
+            .filter(|revision| is_commit_hash(revision));
+        let url = self.file_url(&file, commit.unwrap_or(&file.revision))?;
+        // The file at a commit never changes, so a download from one can be resumed.
+        let mut headers: Vec<(&str, String)> = vec![];
+        if let (Some(_), true) = (commit, offset > 0) {
+            info!("Resuming download of {} from byte {}", resource, offset);
+            headers.push((RANGE.as_str(), format!("bytes={}-", offset)));
+        }
+        let response = self
+            .send(Method::GET, url, &headers, |_, _| false)?
+            .error_for_status()?;
+        let offset = if response.status() == StatusCode::PARTIAL_CONTENT {
+            offset
+        } else {
+            0
+        };
+        let size = response.content_length();
+        let body = RemoteBody::new(response).offset(offset);
+        Ok(match size {
+            Some(size) => body.size(size),
+            None => body,
+        })
+    }
+}
+
+/// A file in a repository on the hub.
+#[derive(Debug, PartialEq)]
+struct HfFile {
+    /// The repository, like `org/repo` or `datasets/org/repo`.
+    repo: String,
+    revision: String,
+    path: String,
+}
+
+impl HfFile {
+    fn parse(resource: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidUrl(String::from(resource));
+        let rest = resource
+            .split_once("://")
+            .map(|(_, rest)| rest)
+            .ok_or_else(invalid)?;
+        let (repo_type, rest) = match rest.split_once('/') {
+            Some((repo_type @ "datasets", rest)) | Some((repo_type @ "spaces", rest)) => {
+                (Some(repo_type), rest)
+            }
+            _ => (None, rest),
+        };
+        let mut parts = rest.splitn(3, '/');
+        let (org, repo, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(org), Some(repo), Some(path)) => (org, repo, path),
+            _ => return Err(invalid()),
+        };
+        let (repo, revision) = match repo.split_once('@') {
+            Some((repo, revision)) => (repo, revision),
+            None => (repo, "main"),
+        };
+        if org.is_empty() || repo.is_empty() || revision.is_empty() || path.is_empty() {
+            return Err(invalid());
+        }
+        let repo = match repo_type {
+            Some(repo_type) => format!("{}/{}/{}", repo_type, org, repo),
+            None => format!("{}/{}", org, repo),
+        };
+        Ok(Self {
+            repo,
+            revision: percent_decode(revision),
+            path: path.into(),
+        })
+    }
+}
+
+fn is_commit_hash(version: &str) -> bool {
+    version.len() == 40 && version.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            HfFile::parse("hf://org/repo/config.json").unwrap(),
+            HfFile {
+                repo: "org/repo".into(),
+                revision: "main".into(),
+                path: "config.json".into(),
+            }
+        );
+        assert_eq!(
+            HfFile::parse("hf://org/repo@v1.0/a/b.bin").unwrap(),
+            HfFile {
+                repo: "org/repo".into(),
+                revision: "v1.0".into(),
+                path: "a/b.bin".into(),
+            }
+        );
+        assert_eq!(
+            HfFile::parse("hf://datasets/org/repo@refs%2Fpr%2F1/data.csv").unwrap(),
+            HfFile {
+                repo: "datasets/org/repo".into(),
+                revision: "refs/pr/1".into(),
+                path: "data.csv".into(),
+            }
+        );
+        for invalid in &[
+            "hf://org/repo",
+            "hf://org/repo/",
+            "hf://org//file",
+            "hf://org/repo@/file",
+        ] {
+            assert!(
+                matches!(HfFile::parse(invalid), Err(Error::InvalidUrl(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_file_url() {
+        let source = HfSource::new().unwrap().endpoint("http://localhost:8080/");
+        let file = HfFile::parse("hf://org/repo@refs%2Fpr%2F1/dir/my file.txt").unwrap();
+        assert_eq!(
+            source.file_url(&file, &file.revision).unwrap().as_str(),
+            "http://localhost:8080/org/repo/resolve/refs%2Fpr%2F1/dir/my%20file.txt"
+        );
+    }
+}
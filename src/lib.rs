@@ -1,4 +1,3 @@
-
 //! The idea behind `cached-path` is to provide a unified, simple interface for
 //! accessing both local and remote files. This can be used behind other APIs that need
 //! to access files agnostic to where they are located.
@@ -94,15 +93,35 @@
 use std::path::PathBuf;
 
 pub(crate) mod archives;
+#[cfg(feature = "async")]
+mod async_cache;
 mod cache;
+mod checksum;
 mod error;
-pub(crate) mod meta;
+mod extraction_policy;
+mod fetch;
+mod gcs;
+mod headers;
+mod hf;
+mod meta;
 mod progress_bar;
+mod remote;
+mod resource;
+mod s3;
 pub(crate) mod utils;
 
-pub use crate::cache::{Cache, CacheBuilder, Options};
-pub use crate::error::Error;
-pub use crate::progress_bar::ProgressBar;
+pub use crate::archives::ArchiveFormat;
+#[cfg(feature = "async")]
+pub use crate::async_cache::{AsyncCache, AsyncCacheBuilder};
+pub use crate::cache::{Cache, CacheBuilder, Options, Request};
+pub use crate::error::{Error, ExtractionErrorKind};
+pub use crate::extraction_policy::{ExtractionPolicy, LinkPolicy, PolicyViolation};
+pub use crate::gcs::GcsSource;
+pub use crate::hf::HfSource;
+pub use crate::meta::Meta;
+pub use crate::progress_bar::{ProgressBar, ProgressReporter};
+pub use crate::remote::{ConditionalFetch, RemoteBody, RemoteMetadata, RemoteSource};
+pub use crate::s3::{S3Credentials, S3Source};
 
 /// Get the cached path to a resource.
 ///
@@ -128,4 +147,4 @@ pub fn cached_path_with_options(resource: &str, options: &Options) -> Result<Pat
 }
 
 #[cfg(test)]
-mod test;
\ No newline at end of file
+mod test;
@@ -1,35 +1,167 @@
-
-use cached_path::{Cache, Error, Options, ProgressBar};
-use color_eyre::eyre::Result;
+use cached_path::{ArchiveFormat, Cache, CacheBuilder, Error, Meta, Options, ProgressBar, Request};
+use color_eyre::eyre::{eyre, Result};
 use log::debug;
-use std::path::PathBuf;
-use std::time::Duration;
+use serde::Deserialize;
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use structopt::clap::{self, AppSettings};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "cached-path",
-    about = "Get the cached path to a resource.",
-    setting = structopt::clap::AppSettings::ColoredHelp,
+    about = "Get the cached path to a resource, or manage the cache.",
+    setting = AppSettings::ColoredHelp,
 )]
 struct Opt {
     #[structopt()]
-    /// The resource path.
-    resource: String,
+    /// The resource path. This is the same as 'cached-path get <resource>'.
+    resource: Option<String>,
 
-    #[structopt(long = "dir", env = "RUST_CACHED_PATH_ROOT")]
+    #[structopt(long = "dir", env = "RUST_CACHED_PATH_ROOT", global = true)]
     /// The cache directory. Defaults to a subdirectory named 'cache' of the default
     /// system temporary directory.
     dir: Option<PathBuf>,
 
+    #[structopt(flatten)]
+    get: GetOpt,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Get the cached path to a resource, downloading it if needed.
+    Get {
+        #[structopt()]
+        /// The resource path.
+        resource: String,
+
+        #[structopt(flatten)]
+        get: GetOpt,
+    },
+
+    /// List the cached resources.
+    Ls {
+        #[structopt(long = "json")]
+        /// Output JSON instead of a table.
+        json: bool,
+    },
+
+    /// Show the details of every cached version of a resource.
+    Info {
+        #[structopt()]
+        /// The resource path.
+        resource: String,
+
+        #[structopt(long = "json")]
+        /// Output JSON.
+        json: bool,
+    },
+
+    /// Remove every cached version of a resource.
+    Rm {
+        #[structopt()]
+        /// The resource path.
+        resource: String,
+    },
+
+    /// Remove cached resources that haven't been used recently, or until the cache is
+    /// under a maximum size.
+    Clean {
+        #[structopt(
+            long = "older-than",
+            parse(try_from_str = parse_duration),
+            required_unless = "max-size"
+        )]
+        /// Remove resources that haven't been used for this long, like '30m', '12h' or '7d'.
+        older_than: Option<Duration>,
+
+        #[structopt(long = "max-size", parse(try_from_str = parse_size))]
+        /// Remove the least-recently-used resources until the cache is under this size,
+        /// like '500M' or '10G'.
+        max_size: Option<u64>,
+    },
+
+    /// Check that the cached resources are intact.
+    Verify,
+
+    /// Fetch every resource listed in a manifest file and print a JSON report of the
+    /// resulting paths and failures.
+    ///
+    /// The manifest is either a JSON file with a list of resources, a TOML file with
+    /// a '[[resources]]' table for each resource, or a text file with a resource on
+    /// every line. Each resource in a JSON or TOML manifest is either a resource path,
+    /// or a table with a 'resource' path and the optional 'subdir', 'extract',
+    /// 'extract_only', and 'checksum' options.
+    Prefetch {
+        #[structopt()]
+        /// The path to the manifest file (.json, .toml, or .txt).
+        manifest: PathBuf,
+
+        #[structopt(short = "j", long = "parallelism", default_value = "4")]
+        /// The maximum number of resources to fetch in parallel.
+        parallelism: usize,
+
+        #[structopt(flatten)]
+        cache: CacheOpt,
+    },
+
+    /// Write cached resources to a bundle that can be imported into another cache,
+    /// such as one on a machine without network access.
+    Export {
+        #[structopt(short = "o", long = "output")]
+        /// The path to write the bundle to. Defaults to stdout.
+        output: Option<PathBuf>,
+
+        #[structopt()]
+        /// The resources to export. Defaults to every cached resource.
+        resources: Vec<String>,
+    },
+
+    /// Add the resources from a bundle created by 'cached-path export' to the cache.
+    Import {
+        #[structopt()]
+        /// The path to the bundle. Defaults to stdin.
+        bundle: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct GetOpt {
+    #[structopt(flatten)]
+    cache: CacheOpt,
+
     #[structopt(long = "subdir")]
     /// The subdirectory, relative to the cache root directory to use.
     subdir: Option<String>,
 
     #[structopt(long = "extract")]
-    /// Extract the resource as an archive.
+    /// Extract the resource as an archive, or decompress it if it's a single compressed file.
     extract: bool,
 
+    #[structopt(long = "archive-format", requires = "extract")]
+    /// The archive format to extract the resource as, like 'tar.gz' or 'zip'.
+    /// By default the format is detected from the contents of the resource.
+    archive_format: Option<ArchiveFormat>,
+
+    #[structopt(long = "extract-only", number_of_values = 1)]
+    /// Only extract the members of the archive that match this glob pattern, like
+    /// 'config/*.json'. Can be given multiple times. Implies '--extract'.
+    extract_only: Vec<String>,
+
+    #[structopt(long = "checksum")]
+    /// Verify the resource against an expected checksum, given as '<algorithm>:<hex digest>'.
+    /// Supported algorithms are sha256, sha512, and blake3.
+    checksum: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+struct CacheOpt {
     #[structopt(long = "timeout")]
     /// Set a request timeout.
     timeout: Option<u64>,
@@ -51,6 +183,16 @@ struct Opt {
     /// Set the a default freshness lifetime (in seconds) for cached resources.
     freshness_lifetime: Option<u64>,
 
+    #[structopt(long = "conditional-get")]
+    /// Check for fresh versions of resources with a single conditional GET request
+    /// instead of a HEAD request followed by a GET request.
+    conditional_get: bool,
+
+    #[structopt(long = "copy-local-files")]
+    /// Copy local files into the cache instead of using them in place, which is useful
+    /// for files on slow network mounts.
+    copy_local_files: bool,
+
     #[structopt(long = "offline")]
     /// Only use offline features.
     offline: bool,
@@ -67,17 +209,371 @@ fn main() -> Result<()> {
 
     debug!("{:?}", opt);
 
-    let cache = build_cache_from_opt(&opt)?;
-    let options = Options::new(opt.subdir.as_deref(), opt.extract);
-    let path = cache.cached_path_with_options(&opt.resource, &options)?;
+    match &opt.cmd {
+        None => match &opt.resource {
+            Some(resource) => get(&opt.dir, resource, &opt.get),
+            None => clap::Error::with_description(
+                "The resource path or a subcommand is required",
+                clap::ErrorKind::MissingRequiredArgument,
+            )
+            .exit(),
+        },
+        Some(Command::Get {
+            resource,
+            get: get_opt,
+        }) => get(&opt.dir, resource, get_opt),
+        Some(Command::Ls { json }) => ls(&open_cache(&opt.dir)?, *json),
+        Some(Command::Info { resource, json }) => info(&open_cache(&opt.dir)?, resource, *json),
+        Some(Command::Rm { resource }) => rm(&open_cache(&opt.dir)?, resource),
+        Some(Command::Clean {
+            older_than,
+            max_size,
+        }) => clean(&open_cache(&opt.dir)?, *older_than, *max_size),
+        Some(Command::Verify) => verify(&open_cache(&opt.dir)?),
+        Some(Command::Prefetch {
+            manifest,
+            parallelism,
+            cache,
+        }) => prefetch(&opt.dir, manifest, *parallelism, cache),
+        Some(Command::Export { output, resources }) => {
+            export(&open_cache(&opt.dir)?, output, resources)
+        }
+        Some(Command::Import { bundle }) => import(&open_cache(&opt.dir)?, bundle),
+    }
+}
+
+fn get(dir: &Option<PathBuf>, resource: &str, opt: &GetOpt) -> Result<()> {
+    let cache = build_cache_from_opt(dir, &opt.cache)?.build()?;
+    let mut options = Options::new(opt.subdir.as_deref(), opt.extract);
+    if let Some(checksum) = &opt.checksum {
+        options = options.checksum(checksum);
+    }
+    if !opt.extract_only.is_empty() {
+        options = options.extract_only(opt.extract_only.iter().cloned());
+    }
+    if let Some(archive_format) = opt.archive_format {
+        options = options.archive_format(archive_format);
+    }
+    let path = cache.cached_path_with_options(resource, &options)?;
     println!("{}", path.to_string_lossy());
 
     Ok(())
 }
 
-fn build_cache_from_opt(opt: &Opt) -> Result<Cache, Error> {
-    let mut cache_builder = Cache::builder().offline(opt.offline);
-    if let Some(dir) = &opt.dir {
+fn ls(cache: &Cache, json: bool) -> Result<()> {
+    let entries: Vec<Meta> = cache.entries().collect();
+    if json {
+        let entries: Vec<_> = entries.iter().map(entry_to_json).collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 4]> = entries
+        .iter()
+        .map(|meta| {
+            [
+                meta.resource().to_string(),
+                format_size(meta.disk_usage()),
+                format_age(meta.creation_time()),
+                meta.etag().unwrap_or("-").to_string(),
+            ]
+        })
+        .collect();
+    let header = [
+        "RESOURCE".to_string(),
+        "SIZE".to_string(),
+        "AGE".to_string(),
+        "ETAG".to_string(),
+    ];
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = std::cmp::max(*width, cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        println!(
+            "{:<w0$}  {:>w1$}  {:>w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+    }
+
+    Ok(())
+}
+
+fn info(cache: &Cache, resource: &str, json: bool) -> Result<()> {
+    let versions = find_versions(cache, resource)?;
+    if json {
+        let versions: Vec<_> = versions.iter().map(entry_to_json).collect();
+        println!("{}", serde_json::to_string_pretty(&versions)?);
+        return Ok(());
+    }
+
+    for (i, meta) in versions.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("resource:      {}", meta.resource());
+        println!("path:          {}", meta.resource_path().to_string_lossy());
+        println!("size:          {}", format_size(meta.disk_usage()));
+        println!("cached:        {} ago", format_age(meta.creation_time()));
+        println!("last access:   {} ago", format_age(meta.last_access()));
+        println!("etag:          {}", meta.etag().unwrap_or("-"));
+        println!("last modified: {}", meta.last_modified().unwrap_or("-"));
+        println!("checksum:      {}", meta.checksum().unwrap_or("-"));
+    }
+
+    Ok(())
+}
+
+fn rm(cache: &Cache, resource: &str) -> Result<()> {
+    for meta in find_versions(cache, resource)? {
+        if cache.remove(&meta)? {
+            println!("{}", meta.resource_path().to_string_lossy());
+        } else {
+            eprintln!(
+                "Skipping {} since it's in use",
+                meta.resource_path().to_string_lossy()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn clean(cache: &Cache, older_than: Option<Duration>, max_size: Option<u64>) -> Result<()> {
+    let mut removed: Vec<Meta> = vec![];
+    if let Some(older_than) = older_than {
+        let cutoff = now() - older_than.as_secs_f64();
+        for meta in cache.entries().filter(|meta| meta.last_access() < cutoff) {
+            if cache.remove(&meta)? {
+                removed.push(meta);
+            }
+        }
+    }
+    if let Some(max_size) = max_size {
+        removed.extend(cache.evict_to(max_size));
+    }
+    for meta in &removed {
+        println!("{}", meta.resource_path().to_string_lossy());
+    }
+
+    Ok(())
+}
+
+fn verify(cache: &Cache) -> Result<()> {
+    let mut failures = 0;
+    for meta in cache.entries() {
+        match cache.verify(&meta) {
+            Ok(()) => println!("ok      {}", meta.resource()),
+            Err(err) => {
+                failures += 1;
+                println!("FAILED  {} ({})", meta.resource(), err);
+            }
+        }
+    }
+    if failures > 0 {
+        Err(eyre!("{} cached resource(s) failed verification", failures))
+    } else {
+        Ok(())
+    }
+}
+
+fn prefetch(
+    dir: &Option<PathBuf>,
+    manifest: &Path,
+    parallelism: usize,
+    opt: &CacheOpt,
+) -> Result<()> {
+    let requests = read_manifest(manifest)?;
+    let cache = build_cache_from_opt(dir, opt)?
+        .prefetch_parallelism(parallelism)
+        .build()?;
+    let results = cache.prefetch(&requests);
+
+    let mut failed = 0;
+    let report: Vec<_> = requests
+        .iter()
+        .zip(results.iter())
+        .map(|(request, result)| match result {
+            Ok(path) => json!({ "resource": request.resource, "path": path }),
+            Err(err) => {
+                failed += 1;
+                json!({ "resource": request.resource, "error": err.to_string() })
+            }
+        })
+        .collect();
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&json!({
+            "succeeded": requests.len() - failed,
+            "failed": failed,
+            "results": report,
+        }))?
+    );
+
+    if failed > 0 {
+        Err(eyre!(
+            "Failed to fetch {} of {} resources",
+            failed,
+            requests.len()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn export(cache: &Cache, output: &Option<PathBuf>, resources: &[String]) -> Result<()> {
+    let resources: Vec<&str> = resources.iter().map(|r| r.as_str()).collect();
+    let exported = match output {
+        Some(output) => {
+            let file = fs::File::create(output)?;
+            cache.export(&resources, io::BufWriter::new(file))?
+        }
+        None => cache.export(&resources, io::stdout().lock())?,
+    };
+    for meta in &exported {
+        eprintln!("Exported {}", meta.resource());
+    }
+
+    Ok(())
+}
+
+fn import(cache: &Cache, bundle: &Option<PathBuf>) -> Result<()> {
+    let imported = match bundle {
+        Some(bundle) => cache.import(io::BufReader::new(fs::File::open(bundle)?))?,
+        None => cache.import(io::stdin().lock())?,
+    };
+    for meta in &imported {
+        println!("{}", meta.resource_path().to_string_lossy());
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Manifest {
+    List(Vec<ManifestEntry>),
+    Table { resources: Vec<ManifestEntry> },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ManifestEntry {
+    Resource(String),
+    Request(ManifestRequest),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestRequest {
+    resource: String,
+    #[serde(default)]
+    subdir: Option<String>,
+    #[serde(default)]
+    extract: bool,
+    #[serde(default)]
+    extract_only: Option<Vec<String>>,
+    #[serde(default)]
+    checksum: Option<String>,
+}
+
+impl From<ManifestEntry> for Request {
+    fn from(entry: ManifestEntry) -> Self {
+        match entry {
+            ManifestEntry::Resource(resource) => Request::new(&resource),
+            ManifestEntry::Request(request) => {
+                let mut options = Options::new(request.subdir.as_deref(), request.extract);
+                if let Some(globs) = request.extract_only {
+                    options = options.extract_only(globs);
+                }
+                if let Some(checksum) = &request.checksum {
+                    options = options.checksum(checksum);
+                }
+                Request::new(&request.resource).options(options)
+            }
+        }
+    }
+}
+
+/// Read the requests from a manifest file, using its extension to determine the format.
+fn read_manifest(path: &Path) -> Result<Vec<Request>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| eyre!("Unable to read manifest {:?} ({})", path, err))?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let manifest = match extension.as_str() {
+        "json" => serde_json::from_str(&contents)
+            .map_err(|err| eyre!("Invalid manifest {:?} ({})", path, err))?,
+        "toml" => toml::from_str(&contents)
+            .map_err(|err| eyre!("Invalid manifest {:?} ({})", path, err))?,
+        _ => Manifest::List(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| ManifestEntry::Resource(line.into()))
+                .collect(),
+        ),
+    };
+    let entries = match manifest {
+        Manifest::List(entries) => entries,
+        Manifest::Table { resources } => resources,
+    };
+    Ok(entries.into_iter().map(Request::from).collect())
+}
+
+/// Find every cached version of a resource, most recent first.
+fn find_versions(cache: &Cache, resource: &str) -> Result<Vec<Meta>> {
+    let mut versions: Vec<Meta> = cache
+        .entries()
+        .filter(|meta| meta.resource() == resource)
+        .collect();
+    if versions.is_empty() {
+        return Err(eyre!("No cached versions of {}", resource));
+    }
+    versions.sort_by(|a, b| b.creation_time().partial_cmp(&a.creation_time()).unwrap());
+    Ok(versions)
+}
+
+fn entry_to_json(meta: &Meta) -> serde_json::Value {
+    json!({
+        "resource": meta.resource(),
+        "path": meta.resource_path(),
+        "size": meta.disk_usage(),
+        "etag": meta.etag(),
+        "last_modified": meta.last_modified(),
+        "checksum": meta.checksum(),
+        "creation_time": meta.creation_time(),
+        "last_access": meta.last_access(),
+        "expires": meta.expires(),
+    })
+}
+
+fn open_cache(dir: &Option<PathBuf>) -> Result<Cache, Error> {
+    let mut cache_builder = Cache::builder().offline(true).progress_bar(None);
+    if let Some(dir) = dir {
+        cache_builder = cache_builder.dir(dir.clone());
+    }
+    cache_builder.build()
+}
+
+fn build_cache_from_opt(dir: &Option<PathBuf>, opt: &CacheOpt) -> Result<CacheBuilder, Error> {
+    let mut cache_builder = Cache::builder()
+        .offline(opt.offline)
+        .conditional_get(opt.conditional_get)
+        .copy_local_files(opt.copy_local_files);
+    if let Some(dir) = dir {
         cache_builder = cache_builder.dir(dir.clone());
     }
     if let Some(timeout) = opt.timeout {
@@ -96,5 +592,85 @@ fn build_cache_from_opt(opt: &Opt) -> Result<Cache, Error> {
     } else {
         cache_builder = cache_builder.progress_bar(None);
     }
-    cache_builder.build()
-}
\ No newline at end of file
+    Ok(cache_builder)
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+/// Parse a duration like '90s', '30m', '12h', '7d', or '2w'. Plain numbers are seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", s))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration '{}'", s)),
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration '{}'", s))
+}
+
+/// Parse a size like '500K', '10MB', or '2GiB'. Units are powers of 1024, and plain
+/// numbers are bytes.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    let unit = unit.to_uppercase();
+    let exponent = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => return Err(format!("invalid size '{}'", s)),
+    };
+    number
+        .checked_mul(1024u64.pow(exponent))
+        .ok_or_else(|| format!("invalid size '{}'", s))
+}
+
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+fn format_age(timestamp: f64) -> String {
+    let seconds = (now() - timestamp).max(0.0) as u64;
+    if seconds < 60 {
+        format!("{}s", seconds)
+    } else if seconds < 60 * 60 {
+        format!("{}m", seconds / 60)
+    } else if seconds < 24 * 60 * 60 {
+        format!("{}h", seconds / (60 * 60))
+    } else {
+        format!("{}d", seconds / (24 * 60 * 60))
+    }
+}
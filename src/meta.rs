@@ -1,14 +1,42 @@
-
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
 
-use crate::utils::now;
+use crate::headers::CacheHeaders;
+use crate::utils::{disk_usage, now};
 use crate::Error;
 
+/// The version of the format that `Meta` is serialized with.
+///
+/// - Version 1 stored absolute paths to the resource and the meta file.
+/// - Version 2 stores the paths relative to the directory of the meta file, so that
+///   a cache directory can be moved or mounted somewhere else.
+///
+/// New optional fields don't need a new version as long as they have a
+/// `#[serde(default)]`, since older versions of the crate keep fields they don't know
+/// about in [`Meta::extra`]. Changes that older meta files have to be rewritten for
+/// need a new version and a migration in [`MIGRATIONS`].
+pub(crate) const META_VERSION: u32 = 2;
+
+/// Upgrades the fields of a serialized meta from one version of the format to the next.
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// The migration at index `i` upgrades version `i + 1` of the format to version `i + 2`.
+const MIGRATIONS: [Migration; META_VERSION as usize - 1] = [migrate_v1_to_v2];
+
 /// Holds information about a cached resource.
+///
+/// Use [`Cache::entries()`](crate::Cache::entries) to iterate over the `Meta` of every
+/// resource in a cache directory.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub(crate) struct Meta {
+pub struct Meta {
+    /// The version of the format this was serialized with. Meta files written before the
+    /// version was recorded are version 1.
+    #[serde(default = "legacy_version")]
+    pub(crate) version: u32,
     /// The original resource name.
     pub(crate) resource: String,
     /// Path to the cached resource.
@@ -21,6 +49,38 @@ pub(crate) struct Meta {
     pub(crate) expires: Option<f64>,
     /// Time this version of the resource was cached.
     pub(crate) creation_time: f64,
+    /// Size of the cached resource in bytes.
+    #[serde(default)]
+    pub(crate) size: Option<u64>,
+    /// Time this version of the resource was last accessed through the cache.
+    #[serde(default)]
+    pub(crate) last_access: Option<f64>,
+    /// The verified checksum of the resource, in the form `<algorithm>:<hex digest>`.
+    #[serde(default)]
+    pub(crate) checksum: Option<String>,
+    /// The `Last-Modified` header of the resource from the time it was cached,
+    /// if there was one.
+    #[serde(default)]
+    pub(crate) last_modified: Option<String>,
+    /// The `Content-Length` header of the resource from the time it was cached,
+    /// if there was one.
+    #[serde(default)]
+    pub(crate) content_length: Option<u64>,
+    /// Whether the server marked the resource as immutable, in which case it's always fresh.
+    #[serde(default)]
+    pub(crate) immutable: bool,
+    /// The `Content-Type` header of the resource from the time it was cached,
+    /// if there was one.
+    #[serde(default)]
+    pub(crate) content_type: Option<String>,
+    /// The file name given by the `Content-Disposition` header of the resource
+    /// from the time it was cached, if there was one.
+    #[serde(default)]
+    pub(crate) filename: Option<String>,
+    /// Fields this version of the crate doesn't know about, such as those added by newer
+    /// versions. They're kept so that they survive when the meta is rewritten.
+    #[serde(flatten)]
+    pub(crate) extra: Map<String, Value>,
 }
 
 impl Meta {
@@ -29,24 +89,134 @@ impl Meta {
         resource_path: PathBuf,
         etag: Option<String>,
         freshness_lifetime: Option<u64>,
+        size: Option<u64>,
     ) -> Meta {
         let mut expires: Option<f64> = None;
         let creation_time = now();
         if let Some(lifetime) = freshness_lifetime {
             expires = Some(creation_time + (lifetime as f64));
         }
-        let meta_path = Meta::meta_path(&resource_path);
+        let meta_path = Meta::meta_path_for(&resource_path);
         Meta {
+            version: META_VERSION,
             resource,
             resource_path,
             meta_path,
             etag,
             expires,
             creation_time,
+            size,
+            last_access: Some(creation_time),
+            checksum: None,
+            last_modified: None,
+            content_length: None,
+            immutable: false,
+            content_type: None,
+            filename: None,
+            extra: Map::new(),
         }
     }
 
-    pub(crate) fn meta_path(resource_path: &Path) -> PathBuf {
+    /// The original resource name.
+    pub fn resource(&self) -> &str {
+        &self.resource
+    }
+
+    /// Path to the cached resource.
+    pub fn resource_path(&self) -> &Path {
+        &self.resource_path
+    }
+
+    /// Path to the serialized meta.
+    pub fn meta_path(&self) -> &Path {
+        &self.meta_path
+    }
+
+    /// The ETAG of the resource from the time it was cached, if there was one.
+    pub fn etag(&self) -> Option<&str> {
+        self.etag.as_deref()
+    }
+
+    /// Time (in seconds since the Unix epoch) that the freshness of this cached resource
+    /// will expire, if it has an expiration time.
+    pub fn expires(&self) -> Option<f64> {
+        self.expires
+    }
+
+    /// Time (in seconds since the Unix epoch) this version of the resource was cached.
+    pub fn creation_time(&self) -> f64 {
+        self.creation_time
+    }
+
+    /// Size of the cached resource in bytes.
+    ///
+    /// This is `None` for resources cached by versions of `cached-path` that didn't record
+    /// the size.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// Time (in seconds since the Unix epoch) this version of the resource was last
+    /// accessed through the cache.
+    ///
+    /// Accesses are only recorded by caches with a
+    /// [`max_size`](crate::CacheBuilder::max_size). Falls back to the creation time for
+    /// resources cached by versions of `cached-path` that didn't record access times.
+    pub fn last_access(&self) -> f64 {
+        self.last_access.unwrap_or(self.creation_time)
+    }
+
+    /// The checksum of the resource that was verified when it was cached, in the form
+    /// `<algorithm>:<hex digest>`.
+    ///
+    /// This is only set when the resource was fetched with
+    /// [`Options::checksum`](crate::Options::checksum).
+    pub fn checksum(&self) -> Option<&str> {
+        self.checksum.as_deref()
+    }
+
+    /// The `Last-Modified` header of the resource from the time it was cached,
+    /// if there was one.
+    pub fn last_modified(&self) -> Option<&str> {
+        self.last_modified.as_deref()
+    }
+
+    /// The `Content-Length` header of the resource from the time it was cached,
+    /// if there was one.
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /// The `Content-Type` header of the resource from the time it was cached,
+    /// if there was one.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// The file name given by the `Content-Disposition` header of the resource
+    /// from the time it was cached, if there was one.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// Whether the server marked the resource as immutable through the `Cache-Control` header,
+    /// in which case the cached version is always considered fresh.
+    pub fn is_immutable(&self) -> bool {
+        self.immutable
+    }
+
+    /// Total size in bytes that this version of the resource takes up on disk, including
+    /// the directories (or files) it has been extracted to.
+    pub fn disk_usage(&self) -> u64 {
+        disk_usage(&self.resource_path)
+            + self
+                .extraction_paths()
+                .iter()
+                .map(|path| disk_usage(path))
+                .sum::<u64>()
+    }
+
+    pub(crate) fn meta_path_for(resource_path: &Path) -> PathBuf {
         let mut meta_path = PathBuf::from(resource_path);
         let resource_file_name = meta_path.file_name().unwrap().to_str().unwrap();
         let meta_file_name = format!("{}.meta", resource_file_name);
@@ -54,47 +224,262 @@ impl Meta {
         meta_path
     }
 
-    pub(crate) fn get_extraction_path(&self) -> PathBuf {
+    /// Path to the directory this resource is extracted to when it's treated as an archive.
+    ///
+    /// The directory only exists if the resource has been extracted. If the resource is
+    /// a single compressed file, this is the path to the decompressed file instead.
+    /// Archives that are extracted with
+    /// [`Options::extract_only()`](crate::Options::extract_only) use a separate
+    /// directory next to this one for every set of patterns.
+    pub fn extraction_path(&self) -> PathBuf {
+        self.extraction_path_with_suffix("-extracted")
+    }
+
+    pub(crate) fn extraction_path_with_suffix(&self, suffix: &str) -> PathBuf {
         let dirname = format!(
-            "{}-extracted",
-            self.resource_path.file_name().unwrap().to_str().unwrap()
+            "{}{}",
+            self.resource_path.file_name().unwrap().to_str().unwrap(),
+            suffix
         );
         self.resource_path.parent().unwrap().join(dirname)
     }
 
+    /// Paths to all of the existing directories (or files) this resource has been
+    /// extracted to.
+    pub(crate) fn extraction_paths(&self) -> Vec<PathBuf> {
+        let pattern = format!(
+            "{}-extracted*",
+            glob::Pattern::escape(self.resource_path.to_str().unwrap())
+        );
+        glob::glob(&pattern)
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|path| path.extension() != Some("lock".as_ref()))
+            .collect()
+    }
+
     pub(crate) fn to_file(&self) -> Result<(), Error> {
-        let serialized = serde_json::to_string(self).unwrap();
-        fs::write(&self.meta_path, &serialized[..])?;
+        // The resource and the meta file always live side by side, so only their file
+        // names are stored.
+        let relative = Meta {
+            resource_path: PathBuf::from(self.resource_path.file_name().unwrap()),
+            meta_path: PathBuf::from(self.meta_path.file_name().unwrap()),
+            ..self.clone()
+        };
+        let serialized = serde_json::to_string(&relative).unwrap();
+        // Write to a temp file first and then rename so that concurrent readers never
+        // see a partially written meta file.
+        let mut tempfile = NamedTempFile::new_in(self.meta_path.parent().unwrap())?;
+        tempfile.write_all(serialized.as_bytes())?;
+        tempfile.persist(&self.meta_path).map_err(|e| e.error)?;
         Ok(())
     }
 
+    /// Record that the resource was just accessed.
+    pub(crate) fn touch(&mut self) -> Result<(), Error> {
+        self.last_access = Some(now());
+        self.to_file()
+    }
+
     pub(crate) fn from_cache(resource_path: &Path) -> Result<Self, Error> {
-        let meta_path = Meta::meta_path(resource_path);
+        let meta_path = Meta::meta_path_for(resource_path);
         Meta::from_path(&meta_path)
     }
 
-    /// Read `Meta` from a path.
+    /// Read `Meta` from a path, migrating it from older versions of the format if needed.
+    ///
+    /// Meta files from newer versions of the format are read as well as possible, since
+    /// any fields they add are optional.
     pub(crate) fn from_path(path: &Path) -> Result<Self, Error> {
         if !path.is_file() {
             return Err(Error::CacheCorrupted(format!("missing meta at {:?}", path)));
         }
+        let invalid =
+            |e: String| Error::CacheCorrupted(format!("invalid meta at {:?}: {}", path, e));
         let serialized = fs::read_to_string(path)?;
-        let meta: Meta = serde_json::from_str(&serialized[..])
-            .map_err(|e| Error::CacheCorrupted(format!("invalid meta at {:?}: {:?}", path, e)))?;
+        let mut fields = match serde_json::from_str(&serialized[..]) {
+            Ok(Value::Object(fields)) => fields,
+            Ok(_) => return Err(invalid("expected an object".into())),
+            Err(e) => return Err(invalid(format!("{:?}", e))),
+        };
+        let version = match fields.get("version") {
+            None => 1,
+            Some(version) => match version.as_u64() {
+                Some(version) if version >= 1 && version <= u32::MAX as u64 => version as u32,
+                _ => return Err(invalid(format!("invalid version {}", version))),
+            },
+        };
+        for migration in MIGRATIONS.iter().skip(version as usize - 1) {
+            migration(&mut fields).map_err(invalid)?;
+        }
+        // Newer meta files keep their version so that newer readers don't try to
+        // migrate them again.
+        fields.insert("version".into(), version.max(META_VERSION).into());
+        let mut meta: Meta = serde_json::from_value(Value::Object(fields))
+            .map_err(|e| invalid(format!("{:?}", e)))?;
+
+        // The paths are relative to wherever the meta file actually is.
+        let resource_file_name = match meta.resource_path.file_name() {
+            Some(file_name) if meta.resource_path.components().count() == 1 => file_name.to_owned(),
+            _ => return Err(invalid("invalid resource path".into())),
+        };
+        meta.resource_path = path.with_file_name(resource_file_name);
+        meta.meta_path = path.to_owned();
         Ok(meta)
     }
 
+    /// Update the freshness and validators of this resource from the headers of a
+    /// response for it.
+    pub(crate) fn update_from_headers(&mut self, headers: &CacheHeaders) {
+        if let Some(expires) = headers.expires {
+            self.expires = Some(self.expires.map_or(expires, |e| e.max(expires)));
+        }
+        self.immutable = headers.immutable;
+        if headers.last_modified.is_some() {
+            self.last_modified = headers.last_modified.clone();
+        }
+        if headers.content_length.is_some() {
+            self.content_length = headers.content_length;
+        }
+        if headers.content_type.is_some() {
+            self.content_type = headers.content_type.clone();
+        }
+        if headers.filename.is_some() {
+            self.filename = headers.filename.clone();
+        }
+    }
+
     /// Check if resource is still fresh. Passing a `Some` value for
-    /// `freshness_lifetime` will override the expiration time (if there is one)
-    /// of this resource.
+    /// `freshness_lifetime` acts as a floor: the resource is fresh if it was cached
+    /// within the past `freshness_lifetime` seconds, or if it hasn't reached its
+    /// expiration time (if there is one).
     pub(crate) fn is_fresh(&self, freshness_lifetime: Option<u64>) -> bool {
+        if self.immutable {
+            return true;
+        }
+        let now = now();
         if let Some(lifetime) = freshness_lifetime {
-            let expiration_time = self.creation_time + (lifetime as f64);
-            expiration_time > now()
-        } else if let Some(expiration_time) = self.expires {
-            expiration_time > now()
-        } else {
-            false
+            if self.creation_time + (lifetime as f64) > now {
+                return true;
+            }
         }
+        matches!(self.expires, Some(expiration_time) if expiration_time > now)
     }
-}
\ No newline at end of file
+}
+
+fn legacy_version() -> u32 {
+    1
+}
+
+/// Version 1 stored absolute paths, which are stale if the cache directory has moved
+/// since. The resource and the meta file are always next to each other, so only the
+/// file names are kept.
+fn migrate_v1_to_v2(fields: &mut Map<String, Value>) -> Result<(), String> {
+    for key in &["resource_path", "meta_path"] {
+        let file_name = fields
+            .get(*key)
+            .and_then(Value::as_str)
+            .and_then(|path| Path::new(path).file_name())
+            .and_then(|file_name| file_name.to_str())
+            .ok_or_else(|| format!("invalid {}", key))?
+            .to_owned();
+        fields.insert((*key).into(), file_name.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_migrate_v1() {
+        let dir = tempdir().unwrap();
+        let meta_path = dir.path().join("abc.def.meta");
+        let v1 = json!({
+            "resource": "https://example.com/hello.txt",
+            "resource_path": "/some/old/cache/abc.def",
+            "meta_path": "/some/old/cache/abc.def.meta",
+            "etag": "def",
+            "expires": null,
+            "creation_time": 1_600_000_000.0,
+        });
+        fs::write(&meta_path, v1.to_string()).unwrap();
+
+        let meta = Meta::from_path(&meta_path).unwrap();
+        assert_eq!(meta.version, META_VERSION);
+        assert_eq!(meta.resource_path, dir.path().join("abc.def"));
+        assert_eq!(meta.meta_path, meta_path);
+        assert_eq!(meta.etag(), Some("def"));
+        assert!(meta.extra.is_empty());
+
+        let mut invalid = v1.clone();
+        invalid.as_object_mut().unwrap().remove("resource_path");
+        fs::write(&meta_path, invalid.to_string()).unwrap();
+        assert!(matches!(
+            Meta::from_path(&meta_path),
+            Err(Error::CacheCorrupted(_))
+        ));
+
+        let mut invalid = v1;
+        invalid["version"] = json!(0);
+        fs::write(&meta_path, invalid.to_string()).unwrap();
+        assert!(matches!(
+            Meta::from_path(&meta_path),
+            Err(Error::CacheCorrupted(_))
+        ));
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let dir = tempdir().unwrap();
+        let meta_path = dir.path().join("abc.meta");
+        let newer = json!({
+            "version": META_VERSION + 1,
+            "resource": "https://example.com/hello.txt",
+            "resource_path": "abc",
+            "meta_path": "abc.meta",
+            "etag": null,
+            "expires": null,
+            "creation_time": 1_600_000_000.0,
+            "some_new_field": { "a": [1, 2, 3] },
+        });
+        fs::write(&meta_path, newer.to_string()).unwrap();
+
+        let mut meta = Meta::from_path(&meta_path).unwrap();
+        assert_eq!(meta.version, META_VERSION + 1);
+        assert_eq!(meta.resource_path, dir.path().join("abc"));
+        assert_eq!(meta.extra["some_new_field"], newer["some_new_field"]);
+
+        // Unknown fields and the newer version survive when the meta is rewritten.
+        meta.touch().unwrap();
+        let rewritten: Value =
+            serde_json::from_str(&fs::read_to_string(&meta_path).unwrap()).unwrap();
+        assert_eq!(rewritten["version"], newer["version"]);
+        assert_eq!(rewritten["some_new_field"], newer["some_new_field"]);
+        assert_eq!(rewritten["resource_path"], "abc");
+        assert!(rewritten["last_access"].is_number());
+    }
+
+    #[test]
+    fn test_resource_path_outside_of_directory() {
+        let dir = tempdir().unwrap();
+        let meta_path = dir.path().join("abc.meta");
+        let meta = json!({
+            "version": META_VERSION,
+            "resource": "https://example.com/hello.txt",
+            "resource_path": "../abc",
+            "meta_path": "abc.meta",
+            "etag": null,
+            "expires": null,
+            "creation_time": 1_600_000_000.0,
+        });
+        fs::write(&meta_path, meta.to_string()).unwrap();
+        assert!(matches!(
+            Meta::from_path(&meta_path),
+            Err(Error::CacheCorrupted(_))
+        ));
+    }
+}
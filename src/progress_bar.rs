@@ -1,14 +1,18 @@
-
+use std::fmt;
 use std::io::{self, Write};
+use std::sync::Arc;
 use std::time::Instant;
 
+use crate::Error;
+
 /// Progress bar types.
 ///
 /// This can be set with
 /// [`CacheBuilder::progress_bar()`](struct.CacheBuilder.html#method.progress_bar).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum ProgressBar {
     /// Gives pretty, verbose progress bars.
+    #[default]
     Full,
     /// Gives progress bars with minimal output.
     ///
@@ -17,29 +21,85 @@ pub enum ProgressBar {
     Light,
 }
 
-impl Default for ProgressBar {
-    fn default() -> Self {
-        ProgressBar::Full
-    }
+/// Receives progress updates for downloads.
+///
+/// This can be set with
+/// [`CacheBuilder::progress_reporter()`](struct.CacheBuilder.html#method.progress_reporter)
+/// to route download progress into your own UI or logs instead of a [`ProgressBar`].
+/// A single reporter is shared by all downloads from a cache, which might run concurrently,
+/// so every update comes with the resource being downloaded.
+///
+/// # Examples
+///
+/// ```rust
+/// use cached_path::{Error, ProgressReporter};
+///
+/// struct LogReporter;
+///
+/// impl ProgressReporter for LogReporter {
+///     fn start(&self, resource: &str, content_length: Option<u64>) {
+///         println!("Downloading {} ({:?} bytes)", resource, content_length);
+///     }
+///
+///     fn tick(&self, _resource: &str, _chunk_size: usize) {}
+///
+///     fn finish(&self, resource: &str, bytes: u64) {
+///         println!("Downloaded {} bytes of {}", bytes, resource);
+///     }
+///
+///     fn error(&self, resource: &str, error: &Error) {
+///         println!("Download of {} failed: {}", resource, error);
+///     }
+/// }
+/// ```
+pub trait ProgressReporter: Send + Sync {
+    /// Called when a download starts. `content_length` is the number of bytes
+    /// to download, if the server reported it.
+    fn start(&self, resource: &str, content_length: Option<u64>);
+
+    /// Called each time a chunk of `chunk_size` bytes is downloaded.
+    fn tick(&self, resource: &str, chunk_size: usize);
+
+    /// Called when a download finishes, with the total number of bytes downloaded.
+    fn finish(&self, resource: &str, bytes: u64);
+
+    /// Called when a download fails. The download may be retried afterwards,
+    /// in which case [`start()`](ProgressReporter::start) is called again.
+    fn error(&self, resource: &str, error: &Error);
 }
 
-impl ProgressBar {
-    pub(crate) fn wrap_download<W: Write>(
-        &self,
-        resource: &str,
-        content_length: Option<u64>,
-        writer: W,
-    ) -> DownloadWrapper<W> {
-        let bar: Box<dyn DownloadBar> = match self {
-            ProgressBar::Full => Box::new(FullDownloadBar::new(content_length)),
-            ProgressBar::Light => Box::new(LightDownloadBar::new(resource, content_length)),
-        };
-        DownloadWrapper::new(bar, writer)
+impl fmt::Debug for dyn ProgressReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressReporter")
     }
 }
 
+/// Wrap a writer to track the progress of a download, reporting it to `progress_reporter`
+/// if there is one, or else showing it with `progress_bar`.
+pub(crate) fn wrap_download<W: Write>(
+    progress_reporter: Option<&Arc<dyn ProgressReporter>>,
+    progress_bar: Option<&ProgressBar>,
+    resource: &str,
+    content_length: Option<u64>,
+    writer: W,
+) -> DownloadWrapper<W> {
+    let bar: Option<Box<dyn DownloadBar>> = match (progress_reporter, progress_bar) {
+        (Some(progress_reporter), _) => Some(Box::new(ReporterDownloadBar::new(
+            progress_reporter.clone(),
+            resource,
+            content_length,
+        ))),
+        (None, Some(ProgressBar::Full)) => Some(Box::new(FullDownloadBar::new(content_length))),
+        (None, Some(ProgressBar::Light)) => {
+            Some(Box::new(LightDownloadBar::new(resource, content_length)))
+        }
+        (None, None) => None,
+    };
+    DownloadWrapper::new(bar, writer)
+}
+
 pub(crate) struct DownloadWrapper<W: Write> {
-    bar: Box<dyn DownloadBar>,
+    bar: Option<Box<dyn DownloadBar>>,
     writer: W,
 }
 
@@ -47,12 +107,20 @@ impl<W> DownloadWrapper<W>
 where
     W: Write,
 {
-    fn new(bar: Box<dyn DownloadBar>, writer: W) -> Self {
+    fn new(bar: Option<Box<dyn DownloadBar>>, writer: W) -> Self {
         Self { bar, writer }
     }
 
     pub(crate) fn finish(&self) {
-        self.bar.finish();
+        if let Some(bar) = &self.bar {
+            bar.finish();
+        }
+    }
+
+    pub(crate) fn error(&self, err: &Error) {
+        if let Some(bar) = &self.bar {
+            bar.error(err);
+        }
     }
 }
 
@@ -71,15 +139,55 @@ impl<W: Write> Write for DownloadWrapper<W> {
 
     fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
         self.writer.write_all(buf).map(|()| {
-            self.bar.tick(buf.len());
+            if let Some(bar) = &mut self.bar {
+                bar.tick(buf.len());
+            }
         })
     }
 }
 
-trait DownloadBar {
+trait DownloadBar: Send {
     fn tick(&mut self, chunk_size: usize);
 
     fn finish(&self);
+
+    fn error(&self, _err: &Error) {}
+}
+
+struct ReporterDownloadBar {
+    progress_reporter: Arc<dyn ProgressReporter>,
+    resource: String,
+    bytes: u64,
+}
+
+impl ReporterDownloadBar {
+    fn new(
+        progress_reporter: Arc<dyn ProgressReporter>,
+        resource: &str,
+        content_length: Option<u64>,
+    ) -> Self {
+        progress_reporter.start(resource, content_length);
+        Self {
+            progress_reporter,
+            resource: resource.into(),
+            bytes: 0,
+        }
+    }
+}
+
+impl DownloadBar for ReporterDownloadBar {
+    fn tick(&mut self, chunk_size: usize) {
+        self.bytes += chunk_size as u64;
+        self.progress_reporter.tick(&self.resource, chunk_size);
+    }
+
+    fn finish(&self) {
+        self.progress_reporter.finish(&self.resource, self.bytes);
+    }
+
+    fn error(&self, err: &Error) {
+        self.progress_reporter.error(&self.resource, err);
+    }
 }
 
 pub(crate) struct FullDownloadBar {
@@ -196,4 +304,4 @@ impl DownloadBar for LightDownloadBar {
         );
         io::stderr().flush().ok();
     }
-}
\ No newline at end of file
+}
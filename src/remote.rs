@@ -0,0 +1,382 @@
+use log::{debug, info, warn};
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, RANGE};
+use reqwest::StatusCode;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::time::SystemTime;
+
+use crate::headers::CacheHeaders;
+use crate::{meta::Meta, Error};
+
+/// A source of remote resources for a URL scheme, like `http` or `s3`.
+///
+/// Sources are registered for a scheme with
+/// [`CacheBuilder::remote_source()`](crate::CacheBuilder::remote_source), and every resource
+/// that starts with `<scheme>://` is then fetched through the source. Sources for `http`,
+/// `https`, `s3`, `gs`, and `hf` are registered by default, and resources with any other
+/// scheme result in an [`UnsupportedScheme`](Error::UnsupportedScheme) error.
+///
+/// The cache takes care of everything else, like locking, retries, checksums, progress
+/// updates, and extraction. Errors are retried with exponential backoff if they're HTTP
+/// errors that are worth retrying, or I/O errors like timeouts and dropped connections.
+///
+/// # Examples
+///
+/// ```rust
+/// use cached_path::{Cache, Error, RemoteBody, RemoteMetadata, RemoteSource};
+/// use std::sync::Arc;
+///
+/// #[derive(Debug)]
+/// struct StaticSource;
+///
+/// impl RemoteSource for StaticSource {
+///     fn metadata(&self, _resource: &str) -> Result<RemoteMetadata, Error> {
+///         Ok(RemoteMetadata::new(Some("v1".into())).size(13))
+///     }
+///
+///     fn fetch(
+///         &self,
+///         _resource: &str,
+///         _metadata: &RemoteMetadata,
+///         _offset: u64,
+///     ) -> Result<RemoteBody, Error> {
+///         Ok(RemoteBody::new(&b"Hello, World!"[..]).size(13))
+///     }
+/// }
+///
+/// let cache = Cache::builder()
+///     .dir(std::env::temp_dir().join("static-source-cache/"))
+///     .remote_source("static", Arc::new(StaticSource))
+///     .build()
+///     .unwrap();
+/// let path = cache.cached_path("static://hello.txt").unwrap();
+/// assert_eq!(std::fs::read_to_string(path).unwrap(), "Hello, World!");
+/// ```
+pub trait RemoteSource: Send + Sync {
+    /// Get the current version of a resource, and its size if it's known.
+    ///
+    /// The version determines when the cached copy of a resource is out-of-date.
+    /// A resource without a version is only ever downloaded once.
+    fn metadata(&self, resource: &str) -> Result<RemoteMetadata, Error>;
+
+    /// Open a stream of the contents of the version of a resource described by `metadata`.
+    ///
+    /// When an interrupted download is resumed, `offset` is the number of bytes that
+    /// were already downloaded. Sources that can't start from the middle of a resource
+    /// can ignore it and return the whole resource, since the offset of the returned
+    /// [`RemoteBody`] is `0` unless it's set with [`RemoteBody::offset()`].
+    fn fetch(
+        &self,
+        resource: &str,
+        metadata: &RemoteMetadata,
+        offset: u64,
+    ) -> Result<RemoteBody, Error>;
+
+    /// Fetch a resource only if it has changed since the `latest` cached version, if there
+    /// is one, with a single request.
+    ///
+    /// This is used when [`CacheBuilder::conditional_get()`](crate::CacheBuilder::conditional_get)
+    /// is enabled. The default implementation returns `None`, which means that the source
+    /// doesn't support conditional requests, so [`metadata()`](RemoteSource::metadata)
+    /// and [`fetch()`](RemoteSource::fetch) are used instead.
+    fn fetch_if_changed(
+        &self,
+        resource: &str,
+        latest: Option<&Meta>,
+    ) -> Result<Option<ConditionalFetch>, Error> {
+        let _ = (resource, latest);
+        Ok(None)
+    }
+}
+
+impl fmt::Debug for dyn RemoteSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RemoteSource")
+    }
+}
+
+/// The version and size of a remote resource, as reported by a [`RemoteSource`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemoteMetadata {
+    pub(crate) headers: CacheHeaders,
+    /// The revision to download this version of the resource from, if the version itself
+    /// doesn't identify where to get it, like the commit of a file on the Hugging Face Hub
+    /// or the version ID of an object in a versioned S3 bucket.
+    pub(crate) revision: Option<String>,
+}
+
+impl RemoteMetadata {
+    /// Create `RemoteMetadata` for the given version of a resource, like an ETAG
+    /// or a revision.
+    pub fn new(version: Option<String>) -> Self {
+        Self {
+            headers: CacheHeaders {
+                etag: version,
+                ..Default::default()
+            },
+            revision: None,
+        }
+    }
+
+    /// Set the size of the resource in bytes.
+    pub fn size(mut self, size: u64) -> Self {
+        self.headers.content_length = Some(size);
+        self
+    }
+
+    pub(crate) fn from_headers(headers: CacheHeaders) -> Self {
+        Self {
+            headers,
+            revision: None,
+        }
+    }
+
+    /// The identifier of this version of the resource, if there is one.
+    pub fn version(&self) -> Option<String> {
+        self.headers.version()
+    }
+
+    /// The size of the resource in bytes, if it's known.
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers.content_length
+    }
+}
+
+/// A stream of the contents of a remote resource, returned by [`RemoteSource::fetch()`].
+pub struct RemoteBody {
+    pub(crate) reader: Box<dyn Read + Send>,
+    pub(crate) offset: u64,
+    pub(crate) size: Option<u64>,
+}
+
+impl RemoteBody {
+    /// Create a `RemoteBody` that reads the contents of a resource from the start.
+    pub fn new<R: Read + Send + 'static>(reader: R) -> Self {
+        Self {
+            reader: Box::new(reader),
+            offset: 0,
+            size: None,
+        }
+    }
+
+    /// Set the offset, in bytes, within the resource that the stream starts at.
+    ///
+    /// This should be the `offset` passed to [`RemoteSource::fetch()`] if the source
+    /// resumed the download from there.
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Set the number of bytes in the stream, used for reporting progress.
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+}
+
+impl fmt::Debug for RemoteBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteBody")
+            .field("offset", &self.offset)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+/// The result of [`RemoteSource::fetch_if_changed()`].
+#[derive(Debug)]
+pub enum ConditionalFetch {
+    /// The latest cached version is still current. The metadata is used to update
+    /// its freshness.
+    NotModified(RemoteMetadata),
+    /// The resource has changed, or there was no cached version.
+    Modified(RemoteMetadata, RemoteBody),
+}
+
+/// The source for `http` and `https` resources.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpSource {
+    client: Client,
+}
+
+impl HttpSource {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+impl RemoteSource for HttpSource {
+    fn metadata(&self, resource: &str) -> Result<RemoteMetadata, Error> {
+        let url = parse_url(resource)?;
+        debug!("Fetching ETAG for {}", url);
+        let response = self.client.head(url.clone()).send()?.error_for_status()?;
+        Ok(metadata_from_headers(&url, response.headers()))
+    }
+
+    fn fetch(
+        &self,
+        resource: &str,
+        metadata: &RemoteMetadata,
+        offset: u64,
+    ) -> Result<RemoteBody, Error> {
+        let url = parse_url(resource)?;
+        debug!("Attempting connection to {}", url);
+
+        let mut response = self
+            .client
+            .get(url.clone())
+            .headers(range_headers(&url, &metadata.headers, offset))
+            .send()?;
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // The partial file is no good, so start over.
+            warn!("Unable to resume download of {}, starting over", url);
+            response = self.client.get(url.clone()).send()?;
+        }
+        let response = response.error_for_status()?;
+
+        debug!("Opened connection to {}", url);
+
+        let offset = response_offset(response.status(), offset);
+        Ok(body_from_response(response).offset(offset))
+    }
+
+    fn fetch_if_changed(
+        &self,
+        resource: &str,
+        latest: Option<&Meta>,
+    ) -> Result<Option<ConditionalFetch>, Error> {
+        let url = parse_url(resource)?;
+        debug!("Sending conditional request for {}", url);
+
+        let response = self
+            .client
+            .get(url)
+            .headers(conditional_headers(latest))
+            .send()?
+            .error_for_status()?;
+        let metadata = RemoteMetadata::from_headers(CacheHeaders::from_headers(response.headers()));
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            Ok(Some(ConditionalFetch::NotModified(metadata)))
+        } else {
+            Ok(Some(ConditionalFetch::Modified(
+                metadata,
+                body_from_response(response),
+            )))
+        }
+    }
+}
+
+/// Get the metadata of a resource from the headers of a response to a `HEAD` request.
+pub(crate) fn metadata_from_headers(url: &reqwest::Url, headers: &HeaderMap) -> RemoteMetadata {
+    let headers = CacheHeaders::from_headers(headers);
+    if headers.etag.is_none() {
+        debug!(
+            "No ETAG for {}, falling back to Last-Modified and Content-Length",
+            url
+        );
+    }
+    RemoteMetadata::from_headers(headers)
+}
+
+/// Get the headers of a request that resumes the download of a resource from `offset`,
+/// which are only set if the response `headers` give a validator to resume with.
+pub(crate) fn range_headers(url: &reqwest::Url, headers: &CacheHeaders, offset: u64) -> HeaderMap {
+    let mut request_headers = HeaderMap::new();
+    let range_validator = headers
+        .range_validator()
+        .and_then(|validator| HeaderValue::from_str(validator).ok());
+    if let (true, Some(range_validator)) = (offset > 0, range_validator) {
+        info!("Resuming download of {} from byte {}", url, offset);
+        request_headers.insert(
+            RANGE,
+            HeaderValue::from_str(&format!("bytes={}-", offset)).unwrap(),
+        );
+        request_headers.insert(IF_RANGE, range_validator);
+    }
+    request_headers
+}
+
+/// Get the offset within a resource that the body of a response to a request made with
+/// [`range_headers()`] starts at. If the server ignored the range request it sends
+/// the whole resource.
+pub(crate) fn response_offset(status: StatusCode, offset: u64) -> u64 {
+    if status == StatusCode::PARTIAL_CONTENT {
+        offset
+    } else {
+        0
+    }
+}
+
+/// Get the headers of a request that only gets a resource if it has changed since
+/// the `latest` cached version.
+pub(crate) fn conditional_headers(latest: Option<&Meta>) -> HeaderMap {
+    let mut request_headers = HeaderMap::new();
+    if let Some(latest) = latest {
+        let etag = latest.etag.as_deref().map(HeaderValue::from_str);
+        if let Some(Ok(etag)) = etag {
+            request_headers.insert(IF_NONE_MATCH, etag);
+        }
+        let last_modified = latest.last_modified.as_deref().map(HeaderValue::from_str);
+        if let Some(Ok(last_modified)) = last_modified {
+            request_headers.insert(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    request_headers
+}
+
+fn parse_url(resource: &str) -> Result<reqwest::Url, Error> {
+    reqwest::Url::parse(resource).map_err(|_| Error::InvalidUrl(String::from(resource)))
+}
+
+fn body_from_response(response: reqwest::blocking::Response) -> RemoteBody {
+    let size = response.content_length();
+    let body = RemoteBody::new(response);
+    match size {
+        Some(size) => body.size(size),
+        None => body,
+    }
+}
+
+/// The source for local files that are copied into the cache, which is used when
+/// [`CacheBuilder::copy_local_files()`](crate::CacheBuilder::copy_local_files) is enabled.
+///
+/// Resources are paths, and the version of a file is its modification time and size.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LocalSource;
+
+impl RemoteSource for LocalSource {
+    fn metadata(&self, resource: &str) -> Result<RemoteMetadata, Error> {
+        let metadata = fs::metadata(resource)
+            .ok()
+            .filter(|metadata| metadata.is_file())
+            .ok_or_else(|| Error::ResourceNotFound(String::from(resource)))?;
+        let modified = metadata
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+        Ok(
+            RemoteMetadata::new(Some(format!("{}-{}", modified, metadata.len())))
+                .size(metadata.len()),
+        )
+    }
+
+    fn fetch(
+        &self,
+        resource: &str,
+        _metadata: &RemoteMetadata,
+        offset: u64,
+    ) -> Result<RemoteBody, Error> {
+        debug!("Copying {} from byte {}", resource, offset);
+        let mut file = File::open(resource)?;
+        let size = file.metadata()?.len();
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(RemoteBody::new(file)
+            .offset(offset)
+            .size(size.saturating_sub(offset)))
+    }
+}
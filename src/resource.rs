@@ -0,0 +1,152 @@
+use reqwest::Url;
+use std::path::{Path, PathBuf};
+
+use crate::utils::home_dir;
+use crate::Error;
+
+/// The scheme of a resource that looks like a URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Scheme {
+    Http,
+    Https,
+    File,
+    /// Any other scheme, like `s3`, in lowercase.
+    Other(String),
+}
+
+impl Scheme {
+    /// Get the scheme of a resource like `s3://bucket/key`, if it has one.
+    pub(crate) fn of(resource: &str) -> Option<Self> {
+        let (scheme, rest) = resource.split_once("://")?;
+        let mut chars = scheme.chars();
+        if !(chars.next()?.is_ascii_alphabetic()
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+            && !rest.is_empty())
+        {
+            return None;
+        }
+        Some(match scheme.to_ascii_lowercase().as_str() {
+            "http" => Scheme::Http,
+            "https" => Scheme::Https,
+            "file" => Scheme::File,
+            other => Scheme::Other(other.into()),
+        })
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+            Scheme::File => "file",
+            Scheme::Other(scheme) => scheme,
+        }
+    }
+}
+
+/// A resource, classified by its scheme.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Resource {
+    /// A URL, which is fetched through the [`RemoteSource`](crate::RemoteSource) registered
+    /// for its scheme. This is never a `file` URL.
+    Url(Scheme),
+    /// A local file, given as a path or a `file://` URL.
+    Local(PathBuf),
+}
+
+impl Resource {
+    /// Parse a resource.
+    ///
+    /// `file://` URLs are converted to paths, which undoes their percent-encoding, and
+    /// a leading `~` in paths is expanded to the home directory. Paths are otherwise
+    /// taken literally.
+    pub(crate) fn parse(resource: &str) -> Result<Self, Error> {
+        match Scheme::of(resource) {
+            Some(Scheme::File) => Url::parse(resource)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .map(Resource::Local)
+                .ok_or_else(|| Error::InvalidUrl(String::from(resource))),
+            Some(scheme) => Ok(Resource::Url(scheme)),
+            None => Ok(Resource::Local(expand_home(resource))),
+        }
+    }
+}
+
+/// Expand a leading `~` in a path to the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(std::path::is_separator) => rest,
+        _ => return PathBuf::from(path),
+    };
+    match home_dir() {
+        Some(home) => home.join(Path::new(rest.trim_start_matches(std::path::is_separator))),
+        None => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheme() {
+        assert_eq!(Scheme::of("https://example.com/a.txt"), Some(Scheme::Https));
+        assert_eq!(Scheme::of("HTTP://example.com/a.txt"), Some(Scheme::Http));
+        assert_eq!(
+            Scheme::of("S3://bucket/key"),
+            Some(Scheme::Other("s3".into()))
+        );
+        assert_eq!(
+            Scheme::of("git+ssh://host/repo")
+                .as_ref()
+                .map(Scheme::as_str),
+            Some("git+ssh")
+        );
+        assert_eq!(Scheme::of("README.md"), None);
+        assert_eq!(Scheme::of("httpdocs.txt"), None);
+        assert_eq!(Scheme::of("C:\\Users\\a.txt"), None);
+        assert_eq!(Scheme::of("dir/a://b"), None);
+        assert_eq!(Scheme::of("https://"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Resource::parse("https://example.com/a.txt").unwrap(),
+            Resource::Url(Scheme::Https)
+        );
+        assert_eq!(
+            Resource::parse("httpdocs.txt").unwrap(),
+            Resource::Local("httpdocs.txt".into())
+        );
+        assert_eq!(
+            Resource::parse("file:///data/my%20file.tar.gz").unwrap(),
+            Resource::Local("/data/my file.tar.gz".into())
+        );
+        assert_eq!(
+            Resource::parse("file://localhost/data/x.txt").unwrap(),
+            Resource::Local("/data/x.txt".into())
+        );
+        // Only paths in file URLs are percent-decoded.
+        assert_eq!(
+            Resource::parse("/data/my%20file.txt").unwrap(),
+            Resource::Local("/data/my%20file.txt".into())
+        );
+        assert!(matches!(
+            Resource::parse("file://server/data/x.txt"),
+            Err(Error::InvalidUrl(_))
+        ));
+
+        let home = home_dir().unwrap();
+        assert_eq!(Resource::parse("~").unwrap(), Resource::Local(home.clone()));
+        assert_eq!(
+            Resource::parse("~/data/x.txt").unwrap(),
+            Resource::Local(home.join("data/x.txt"))
+        );
+        assert_eq!(
+            Resource::parse("~user/x.txt").unwrap(),
+            Resource::Local("~user/x.txt".into())
+        );
+    }
+}
@@ -1,9 +1,15 @@
-use crate::{meta::Meta, Cache, Options};
+use crate::{
+    meta::Meta, ArchiveFormat, Cache, Error, ExtractionErrorKind, Options, ProgressReporter,
+    RemoteBody, RemoteMetadata, RemoteSource, Request,
+};
+use fs2::FileExt;
 use httpmock::Method::{GET, HEAD};
 use httpmock::{MockRef, MockServer};
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use tempfile::tempdir;
 
 static ETAG_KEY: &str = "ETag";
@@ -73,6 +79,97 @@ fn test_get_cached_path_non_existant_local_file_fails() {
     assert!(result.is_err());
 }
 
+#[test]
+fn test_cached_path_file_url() {
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    // File URLs are percent-decoded.
+    let data_dir = tempdir().unwrap();
+    let file_path = data_dir.path().join("my file.txt");
+    fs::write(&file_path, "Hello, World!").unwrap();
+    let url = reqwest::Url::from_file_path(&file_path).unwrap();
+    assert!(url.as_str().ends_with("/my%20file.txt"));
+    assert_eq!(cache.cached_path(url.as_str()).unwrap(), file_path);
+    assert!(matches!(
+        cache.cached_path(&format!("{}.missing", url)),
+        Err(Error::ResourceNotFound(_))
+    ));
+
+    // Local archives are extracted once per version.
+    let archive = Path::new("test_fixtures/hello.txt.gz")
+        .canonicalize()
+        .unwrap();
+    let url = reqwest::Url::from_file_path(&archive).unwrap();
+    let options = Options::default().extract();
+    let path = cache
+        .cached_path_with_options(url.as_str(), &options)
+        .unwrap();
+    assert!(path.starts_with(cache_dir.path()));
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        fs::read_to_string("test_fixtures/hello.txt").unwrap()
+    );
+    assert_eq!(
+        cache
+            .cached_path_with_options(archive.to_str().unwrap(), &options)
+            .unwrap(),
+        path
+    );
+}
+
+#[test]
+fn test_copy_local_files() {
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .copy_local_files(true)
+        .build()
+        .unwrap();
+
+    let data_dir = tempdir().unwrap();
+    let file_path = data_dir.path().join("data.txt");
+    fs::write(&file_path, "Hello, World!").unwrap();
+    let path = cache.cached_path(file_path.to_str().unwrap()).unwrap();
+    assert!(path.starts_with(cache_dir.path()));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "Hello, World!");
+    let meta = Meta::from_cache(&path).unwrap();
+    assert_eq!(
+        meta.resource,
+        file_path.canonicalize().unwrap().to_str().unwrap()
+    );
+    assert_eq!(meta.content_length(), Some(13));
+
+    // Unchanged files aren't copied again, however they're referred to.
+    let modified = path.metadata().unwrap().modified().unwrap();
+    let url = reqwest::Url::from_file_path(&file_path).unwrap();
+    assert_eq!(cache.cached_path(url.as_str()).unwrap(), path);
+    assert_eq!(path.metadata().unwrap().modified().unwrap(), modified);
+
+    // Changed files are copied again.
+    fs::write(&file_path, "Hello, everyone!").unwrap();
+    let new_path = cache.cached_path(file_path.to_str().unwrap()).unwrap();
+    assert_ne!(new_path, path);
+    assert_eq!(fs::read_to_string(&new_path).unwrap(), "Hello, everyone!");
+
+    // Copies are checked against checksums.
+    let result = cache.cached_path_with_options(
+        file_path.to_str().unwrap(),
+        &Options::default().checksum(&format!("sha256:{:x}", Sha256::digest(b"Hello!"))),
+    );
+    assert!(matches!(result, Err(Error::ChecksumMismatch { .. })));
+
+    assert!(matches!(
+        cache.cached_path(data_dir.path().join("missing.txt").to_str().unwrap()),
+        Err(Error::ResourceNotFound(_))
+    ));
+}
+
 #[test]
 fn test_cached_path_remote_file() {
     // For debugging:
@@ -100,7 +197,7 @@ fn test_cached_path_remote_file() {
 
     // Ensure the file and meta exist.
     assert!(path.is_file());
-    assert!(Meta::meta_path(&path).is_file());
+    assert!(Meta::meta_path_for(&path).is_file());
     let mut meta = Meta::from_cache(&path).unwrap();
     assert_eq!(meta.etag.as_deref(), Some("fake-etag"));
 
@@ -113,7 +210,7 @@ fn test_cached_path_remote_file() {
     let same_path = cache.cached_path(resource).unwrap();
     assert_eq!(same_path, path);
     assert!(path.is_file());
-    assert!(Meta::meta_path(&path).is_file());
+    assert!(Meta::meta_path_for(&path).is_file());
 
     // Didn't have to call HEAD or GET again.
     assert_eq!(fixture.head.hits(), 1);
@@ -136,7 +233,7 @@ fn test_cached_path_remote_file() {
     let same_path = cache.cached_path(resource).unwrap();
     assert_eq!(same_path, path);
     assert!(path.is_file());
-    assert!(Meta::meta_path(&path).is_file());
+    assert!(Meta::meta_path_for(&path).is_file());
     assert_eq!(fixture.head.hits(), 2);
     assert_eq!(fixture.get.hits(), 1);
 
@@ -155,7 +252,7 @@ fn test_cached_path_remote_file() {
 
     // Ensure the file and meta exist.
     assert!(new_path.is_file());
-    assert!(Meta::meta_path(&new_path).is_file());
+    assert!(Meta::meta_path_for(&new_path).is_file());
 
     // Ensure the contents of the file are correct.
     let new_contents = fs::read_to_string(&new_path).unwrap().replace("\r\n", "\n");
@@ -189,11 +286,14 @@ fn test_cached_path_remote_file_in_subdir() {
 
     // Ensure the file and meta exist.
     assert!(path.is_file());
-    assert!(Meta::meta_path(&path).is_file());
+    assert!(Meta::meta_path_for(&path).is_file());
 }
 
 #[test]
-fn test_extract_tar_gz() {
+fn test_cache_entries() {
+    let server = MockServer::start();
+
+    // Setup cache.
     let cache_dir = tempdir().unwrap();
     let cache = Cache::builder()
         .dir(cache_dir.path().to_owned())
@@ -201,63 +301,118 @@ fn test_extract_tar_gz() {
         .build()
         .unwrap();
 
-    let resource: PathBuf = [
-        ".",
-        "test_fixtures",
-        "utf-8_sample",
-        "archives",
-        "utf-8.tar.gz",
-    ]
-    .iter()
-    .collect();
+    // An empty cache has no entries.
+    assert_eq!(cache.entries().count(), 0);
 
-    let path = cache
-        .cached_path_with_options(resource.to_str().unwrap(), &Options::default().extract())
+    // Mock the resources.
+    let hello = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+    let sample = Fixture::load(
+        &server,
+        "test_fixtures/utf-8_sample/utf-8_sample.txt",
+        "fake-etag",
+    );
+
+    // Cache one resource in the root and the other in a subdirectory.
+    let hello_path = cache.cached_path(&hello.url).unwrap();
+    let sample_path = cache
+        .cached_path_with_options(&sample.url, &Options::default().subdir("target"))
         .unwrap();
-    assert!(path.is_dir());
-    assert!(path.to_str().unwrap().ends_with("-extracted"));
-    assert!(path
+
+    // Extraction directories shouldn't show up as entries.
+    cache
+        .cached_path_with_options(
+            "test_fixtures/utf-8_sample/archives/utf-8.tar.gz",
+            &Options::default().extract(),
+        )
+        .unwrap();
+
+    let mut entries: Vec<Meta> = cache.entries().collect();
+    entries.sort_by(|a, b| a.resource().cmp(b.resource()));
+    assert_eq!(entries.len(), 2);
+
+    assert_eq!(entries[0].resource(), hello.url);
+    assert_eq!(entries[0].resource_path(), hello_path);
+    assert_eq!(entries[0].meta_path(), Meta::meta_path_for(&hello_path));
+    assert_eq!(entries[0].etag(), Some("fake-etag"));
+    assert_eq!(entries[0].size(), Some(14));
+
+    assert_eq!(entries[1].resource(), sample.url);
+    assert_eq!(entries[1].resource_path(), sample_path);
+    assert!(entries[1]
+        .extraction_path()
         .to_str()
         .unwrap()
-        .starts_with(cache_dir.path().to_str().unwrap()));
-    let sample_file_path = path.join("dummy.txt");
-    assert!(sample_file_path.is_file());
+        .ends_with("-extracted"));
 }
 
 #[test]
-fn test_extract_zip() {
+fn test_checksum() {
+    let server = MockServer::start();
+
+    // Setup cache.
     let cache_dir = tempdir().unwrap();
     let cache = Cache::builder()
         .dir(cache_dir.path().to_owned())
         .progress_bar(None)
+        .freshness_lifetime(300)
         .build()
         .unwrap();
 
-    let resource: PathBuf = [
-        ".",
-        "test_fixtures",
-        "utf-8_sample",
-        "archives",
-        "utf-8.zip",
-    ]
-    .iter()
-    .collect();
+    // Mock the resource.
+    let fixture = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+    let sha256 = format!(
+        "sha256:{:x}",
+        Sha256::digest(fs::read("test_fixtures/hello.txt").unwrap())
+    );
+    let sha256 = sha256.as_str();
+    let wrong = "sha256:0000000000000000000000000000000000000000000000000000000000000000";
+
+    // A mismatched checksum fails and nothing gets cached.
+    let err = cache
+        .cached_path_with_options(&fixture.url, &Options::default().checksum(wrong))
+        .unwrap_err();
+    assert!(matches!(err, Error::ChecksumMismatch { .. }));
+    assert_eq!(cache.entries().count(), 0);
 
+    // A matching checksum is recorded in the meta.
     let path = cache
-        .cached_path_with_options(resource.to_str().unwrap(), &Options::default().extract())
+        .cached_path_with_options(&fixture.url, &Options::default().checksum(sha256))
         .unwrap();
-    assert!(path.is_dir());
-    assert!(path.to_str().unwrap().ends_with("-extracted"));
-    assert!(path
-        .to_str()
-        .unwrap()
-        .starts_with(cache_dir.path().to_str().unwrap()));
-    let sample_file_path = path.join("dummy.txt");
-    assert!(sample_file_path.is_file());
+    let meta = Meta::from_cache(&path).unwrap();
+    assert_eq!(meta.checksum(), Some(sha256));
+    assert_eq!(fixture.get.hits(), 2);
+
+    // Cache hits are checked against the expected checksum too.
+    let err = cache
+        .cached_path_with_options(&fixture.url, &Options::default().checksum(wrong))
+        .unwrap_err();
+    assert!(matches!(err, Error::ChecksumMismatch { .. }));
+    let wrong_blake3 = "blake3:6d4279f9ff6a6e91ed9cf8d71d9a4ba4b5fd3bfc4bc0dd6e8c53c1dac8b0d4d7";
+    assert!(cache
+        .cached_path_with_options(&fixture.url, &Options::default().checksum(wrong_blake3))
+        .is_err());
+    assert_eq!(fixture.get.hits(), 2);
+
+    // Invalid checksums are rejected.
+    let err = cache
+        .cached_path_with_options(&fixture.url, &Options::default().checksum("sha256"))
+        .unwrap_err();
+    assert!(matches!(err, Error::InvalidChecksum(_)));
+
+    // Local files can be verified too.
+    assert!(cache
+        .cached_path_with_options(
+            "test_fixtures/hello.txt",
+            &Options::default().checksum(wrong)
+        )
+        .is_err());
 }
 
 #[test]
-fn test_extract_in_subdir() {
+fn test_resume_download() {
+    let server = MockServer::start();
+
+    // Setup cache.
     let cache_dir = tempdir().unwrap();
     let cache = Cache::builder()
         .dir(cache_dir.path().to_owned())
@@ -265,29 +420,2375 @@ fn test_extract_in_subdir() {
         .build()
         .unwrap();
 
-    let resource: PathBuf = [
-        ".",
-        "test_fixtures",
-        "utf-8_sample",
-        "archives",
-        "utf-8.tar.gz",
-    ]
-    .iter()
-    .collect();
+    // Mock a server that supports range requests.
+    let head = server.mock(|when, then| {
+        when.method(HEAD).path("/hello.txt");
+        then.status(200).header(ETAG_KEY, "fake-etag");
+    });
+    let get_range = server.mock(|when, then| {
+        when.method(GET)
+            .path("/hello.txt")
+            .header("Range", "bytes=7-")
+            .header("If-Range", "fake-etag");
+        then.status(206)
+            .header(ETAG_KEY, "fake-etag")
+            .header("Content-Range", "bytes 7-13/14")
+            .body("World!\n");
+    });
+    let resource = server.url("/hello.txt");
 
-    let path = cache
-        .cached_path_with_options(
-            resource.to_str().unwrap(),
-            &Options::default().subdir("target").extract(),
-        )
+    // Simulate a previous download that got interrupted.
+    let path = cache.resource_to_filepath(&resource, &Some("fake-etag".into()), None, None);
+    let partial_path = Cache::partial_path(&path);
+    fs::write(&partial_path, "Hello, ").unwrap();
+
+    let checksum = format!("sha256:{:x}", Sha256::digest(b"Hello, World!\n"));
+    let cached_path = cache
+        .cached_path_with_options(&resource, &Options::default().checksum(&checksum))
         .unwrap();
-    assert!(path.is_dir());
-    assert!(path.to_str().unwrap().ends_with("-extracted"));
-    assert!(path.parent().unwrap().to_str().unwrap().ends_with("target"));
-    assert!(path
-        .to_str()
-        .unwrap()
-        .starts_with(cache_dir.path().to_str().unwrap()));
-    let sample_file_path = path.join("dummy.txt");
-    assert!(sample_file_path.is_file());
+    assert_eq!(cached_path, path);
+    assert_eq!(head.hits(), 1);
+    assert_eq!(get_range.hits(), 1);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "Hello, World!\n");
+    assert!(!partial_path.exists());
+    assert_eq!(Meta::from_cache(&path).unwrap().size(), Some(14));
+}
+
+#[test]
+fn test_resume_download_falls_back_to_full_download() {
+    let server = MockServer::start();
+
+    // Setup cache.
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    // Mock the resource. The server ignores range requests.
+    let fixture = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+
+    // Simulate a previous download that got interrupted.
+    let path = cache.resource_to_filepath(&fixture.url, &Some("fake-etag".into()), None, None);
+    let partial_path = Cache::partial_path(&path);
+    fs::write(&partial_path, "Goodbye").unwrap();
+
+    let cached_path = cache.cached_path(&fixture.url).unwrap();
+    assert_eq!(cached_path, path);
+    assert_eq!(fixture.get.hits(), 1);
+    let contents = fs::read_to_string(&path).unwrap().replace("\r\n", "\n");
+    assert_eq!(&contents, "Hello, World!\n");
+    assert!(!partial_path.exists());
+}
+
+#[test]
+fn test_conditional_get() {
+    let server = MockServer::start();
+
+    // Setup cache.
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .conditional_get(true)
+        .build()
+        .unwrap();
+
+    // Mock the resource. Conditional requests for the current version get a 304.
+    let last_modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+    let mut not_modified = server.mock(|when, then| {
+        when.method(GET)
+            .path("/hello.txt")
+            .header("If-None-Match", "fake-etag")
+            .header("If-Modified-Since", "Wed, 21 Oct 2015 07:28:00 GMT");
+        then.status(304).header(ETAG_KEY, "fake-etag");
+    });
+    let mut get = server.mock(|when, then| {
+        when.method(GET).path("/hello.txt");
+        then.status(200)
+            .header(ETAG_KEY, "fake-etag")
+            .header("Last-Modified", last_modified)
+            .body("Hello, World!\n");
+    });
+    let head = server.mock(|when, then| {
+        when.method(HEAD).path("/hello.txt");
+        then.status(405);
+    });
+    let resource = server.url("/hello.txt");
+
+    // The first request downloads the resource.
+    let path = cache.cached_path(&resource).unwrap();
+    assert_eq!(get.hits(), 1);
+    assert_eq!(not_modified.hits(), 0);
+    let meta = Meta::from_cache(&path).unwrap();
+    assert_eq!(meta.etag(), Some("fake-etag"));
+    assert_eq!(meta.last_modified(), Some(last_modified));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "Hello, World!\n");
+
+    // The second request is answered with a 304.
+    let same_path = cache.cached_path(&resource).unwrap();
+    assert_eq!(same_path, path);
+    assert_eq!(get.hits(), 1);
+    assert_eq!(not_modified.hits(), 1);
+
+    // Now update the resource.
+    not_modified.delete();
+    get.delete();
+    let get = server.mock(|when, then| {
+        when.method(GET).path("/hello.txt");
+        then.status(200)
+            .header(ETAG_KEY, "fake-etag-2")
+            .body("Goodbye, World!\n");
+    });
+
+    let new_path = cache.cached_path(&resource).unwrap();
+    assert_ne!(new_path, path);
+    assert_eq!(get.hits(), 1);
+    assert_eq!(fs::read_to_string(&new_path).unwrap(), "Goodbye, World!\n");
+
+    // HEAD was never used.
+    assert_eq!(head.hits(), 0);
+}
+
+#[derive(Default)]
+struct RecordingReporter {
+    events: Mutex<Vec<String>>,
+}
+
+impl ProgressReporter for RecordingReporter {
+    fn start(&self, resource: &str, content_length: Option<u64>) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("start {} {:?}", resource, content_length));
+    }
+
+    fn tick(&self, _resource: &str, chunk_size: usize) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("tick {}", chunk_size));
+    }
+
+    fn finish(&self, resource: &str, bytes: u64) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("finish {} {}", resource, bytes));
+    }
+
+    fn error(&self, resource: &str, error: &Error) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("error {} {}", resource, error));
+    }
+}
+
+#[test]
+fn test_progress_reporter() {
+    let server = MockServer::start();
+
+    // Setup cache.
+    let cache_dir = tempdir().unwrap();
+    let reporter = Arc::new(RecordingReporter::default());
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_reporter(reporter.clone())
+        .build()
+        .unwrap();
+
+    // Mock the resource.
+    let fixture = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+    let resource = fixture.url.as_str();
+
+    cache.cached_path(resource).unwrap();
+    let events = reporter.events.lock().unwrap().clone();
+    assert_eq!(
+        events.first().unwrap(),
+        &format!("start {} Some(14)", resource)
+    );
+    assert_eq!(events.last().unwrap(), &format!("finish {} 14", resource));
+    let ticked: usize = events
+        .iter()
+        .filter_map(|event| event.strip_prefix("tick "))
+        .map(|chunk_size| chunk_size.parse::<usize>().unwrap())
+        .sum();
+    assert_eq!(ticked, 14);
+}
+
+#[test]
+fn test_cache_control_headers() {
+    let server = MockServer::start();
+
+    // Setup cache.
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    // Mock resources with different caching headers.
+    let mut mocks = vec![];
+    for (name, cache_control) in &[
+        ("max-age.txt", "public, max-age=300"),
+        ("immutable.txt", "max-age=0, immutable"),
+        ("no-cache.txt", "max-age=300, no-cache"),
+    ] {
+        let path = format!("/{}", name);
+        let head = server.mock(|when, then| {
+            when.method(HEAD).path(&path);
+            then.status(200)
+                .header(ETAG_KEY, "fake-etag")
+                .header("Cache-Control", cache_control);
+        });
+        let get = server.mock(|when, then| {
+            when.method(GET).path(&path);
+            then.status(200)
+                .header(ETAG_KEY, "fake-etag")
+                .header("Cache-Control", cache_control)
+                .body("Hello, World!\n");
+        });
+        mocks.push((server.url(&path), head, get));
+    }
+
+    for (resource, _, _) in &mocks {
+        cache.cached_path(resource).unwrap();
+        cache.cached_path(resource).unwrap();
+    }
+
+    // Resources with a 'max-age' or that are immutable are only checked once.
+    let (_, head, get) = &mocks[0];
+    assert_eq!(head.hits(), 1);
+    assert_eq!(get.hits(), 1);
+    let (resource, head, get) = &mocks[1];
+    assert_eq!(head.hits(), 1);
+    assert_eq!(get.hits(), 1);
+    assert!(cache
+        .entries()
+        .any(|meta| &meta.resource == resource && meta.is_immutable()));
+
+    // Resources with 'no-cache' are checked every time.
+    let (_, head, get) = &mocks[2];
+    assert_eq!(head.hits(), 2);
+    assert_eq!(get.hits(), 1);
+}
+
+#[test]
+fn test_last_modified_fallback() {
+    let server = MockServer::start();
+
+    // Setup cache.
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    // Mock a resource without an ETAG.
+    let mock_resource = |last_modified: &'static str, body: &'static str| {
+        let head = server.mock(|when, then| {
+            when.method(HEAD).path("/hello.txt");
+            then.status(200)
+                .header("Last-Modified", last_modified)
+                .header("Content-Length", &body.len().to_string());
+        });
+        let get = server.mock(|when, then| {
+            when.method(GET).path("/hello.txt");
+            then.status(200)
+                .header("Last-Modified", last_modified)
+                .body(body);
+        });
+        (head, get)
+    };
+    let resource = server.url("/hello.txt");
+
+    let (mut head, mut get) = mock_resource("Wed, 21 Oct 2015 07:28:00 GMT", "Hello, World!\n");
+    let path = cache.cached_path(&resource).unwrap();
+    let meta = Meta::from_cache(&path).unwrap();
+    assert_eq!(meta.etag(), None);
+    assert_eq!(meta.last_modified(), Some("Wed, 21 Oct 2015 07:28:00 GMT"));
+    assert_eq!(meta.content_length(), Some(14));
+
+    // The resource hasn't changed, so it's not downloaded again.
+    assert_eq!(cache.cached_path(&resource).unwrap(), path);
+    assert_eq!(head.hits(), 2);
+    assert_eq!(get.hits(), 1);
+
+    // Now update the resource.
+    head.delete();
+    get.delete();
+    let (_head, get) = mock_resource("Thu, 22 Oct 2015 07:28:00 GMT", "Goodbye, World!\n");
+    let new_path = cache.cached_path(&resource).unwrap();
+    assert_ne!(new_path, path);
+    assert_eq!(get.hits(), 1);
+    assert_eq!(fs::read_to_string(&new_path).unwrap(), "Goodbye, World!\n");
+}
+
+#[test]
+fn test_keep_versions() {
+    let server = MockServer::start();
+
+    // Setup cache.
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .keep_versions(2)
+        .build()
+        .unwrap();
+
+    // Cache three versions of the same resource.
+    let mut paths: Vec<PathBuf> = vec![];
+    for etag in &["fake-etag-1", "fake-etag-2", "fake-etag-3"] {
+        let fixture = Fixture::load(&server, "test_fixtures/hello.txt", etag);
+        paths.push(cache.cached_path(&fixture.url).unwrap());
+    }
+
+    // Only the newest two versions should be left.
+    assert!(!paths[0].exists());
+    assert!(!Meta::meta_path_for(&paths[0]).exists());
+    // The lock file is left in place for anyone who's still waiting on it.
+    assert!(Path::new(&format!("{}.lock", paths[0].to_str().unwrap())).exists());
+    assert!(paths[1].is_file());
+    assert!(paths[2].is_file());
+    assert_eq!(cache.entries().count(), 2);
+}
+
+#[test]
+fn test_remove_verify_and_evict_to() {
+    let server = MockServer::start();
+
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    let fixture = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+    let path = cache.cached_path(&fixture.url).unwrap();
+    let meta = cache.entries().next().unwrap();
+    assert!(meta.disk_usage() > 0);
+
+    // Verification catches files that were modified after they were cached.
+    cache.verify(&meta).unwrap();
+    fs::write(&path, "Hello!").unwrap();
+    assert!(matches!(cache.verify(&meta), Err(Error::CacheCorrupted(_))));
+
+    assert!(cache.remove(&meta).unwrap());
+    assert!(!path.exists());
+    assert!(!meta.meta_path().exists());
+    assert_eq!(cache.entries().count(), 0);
+
+    let path = cache.cached_path(&fixture.url).unwrap();
+    assert!(cache.evict_to(1024).is_empty());
+    let evicted = cache.evict_to(0);
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0].resource_path(), path);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_prefetch() {
+    let server = MockServer::start();
+
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .max_retries(0)
+        .prefetch_parallelism(2)
+        .build()
+        .unwrap();
+
+    let hello = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+    let sample = Fixture::load(
+        &server,
+        "test_fixtures/utf-8_sample/utf-8_sample.txt",
+        "fake-etag",
+    );
+    let contents = fs::read("test_fixtures/utf-8_sample/archives/utf-8.tar.gz").unwrap();
+    server.mock(|when, then| {
+        when.method(HEAD).path("/utf-8.tar.gz");
+        then.status(200).header(ETAG_KEY, "fake-etag");
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/utf-8.tar.gz");
+        then.status(200)
+            .header(ETAG_KEY, "fake-etag")
+            .body(&contents);
+    });
+    server.mock(|when, then| {
+        when.path("/missing.txt");
+        then.status(404);
+    });
+
+    let results = cache.prefetch(&[
+        Request::new(&hello.url),
+        Request::new(&server.url("/missing.txt")),
+        Request::new(&sample.url).options(Options::default().subdir("target")),
+        Request::new(&server.url("/utf-8.tar.gz")).options(Options::default().extract()),
+        Request::new("test_fixtures/hello.txt"),
+    ]);
+    assert_eq!(results.len(), 5);
+    assert_eq!(
+        fs::read_to_string(results[0].as_ref().unwrap()).unwrap(),
+        fs::read_to_string("test_fixtures/hello.txt").unwrap()
+    );
+    assert!(matches!(results[1], Err(Error::HttpError(_))));
+    assert!(results[2]
+        .as_ref()
+        .unwrap()
+        .starts_with(cache_dir.path().join("target")));
+    assert!(results[3].as_ref().unwrap().join("dummy.txt").is_file());
+    assert_eq!(
+        results[4].as_ref().unwrap(),
+        Path::new("test_fixtures/hello.txt")
+    );
+    hello.get.assert_hits(1);
+    sample.get.assert_hits(1);
+
+    assert!(cache.prefetch(&[]).is_empty());
+}
+
+/// A [`RemoteSource`] that serves resources from memory.
+#[derive(Default)]
+struct MemorySource {
+    resources: Mutex<std::collections::HashMap<String, (String, Vec<u8>)>>,
+    /// If set, the next body is cut off after this many bytes.
+    cut_off_at: Mutex<Option<usize>>,
+    metadata_calls: std::sync::atomic::AtomicUsize,
+    /// The offsets that bodies were fetched from.
+    fetches: Mutex<Vec<u64>>,
+}
+
+impl MemorySource {
+    fn put(&self, resource: &str, version: &str, contents: &[u8]) {
+        self.resources
+            .lock()
+            .unwrap()
+            .insert(resource.into(), (version.into(), contents.into()));
+    }
+}
+
+impl RemoteSource for MemorySource {
+    fn metadata(&self, resource: &str) -> Result<RemoteMetadata, Error> {
+        self.metadata_calls
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        match self.resources.lock().unwrap().get(resource) {
+            Some((version, contents)) => {
+                Ok(RemoteMetadata::new(Some(version.clone())).size(contents.len() as u64))
+            }
+            None => Err(Error::ResourceNotFound(resource.into())),
+        }
+    }
+
+    fn fetch(
+        &self,
+        resource: &str,
+        metadata: &RemoteMetadata,
+        offset: u64,
+    ) -> Result<RemoteBody, Error> {
+        self.fetches.lock().unwrap().push(offset);
+        let (version, contents) = self.resources.lock().unwrap()[resource].clone();
+        assert_eq!(metadata.version(), Some(version));
+        let contents = contents[offset as usize..].to_vec();
+        let body: Box<dyn std::io::Read + Send> = match self.cut_off_at.lock().unwrap().take() {
+            Some(cut_off_at) => Box::new(std::io::Read::chain(
+                std::io::Cursor::new(contents[..cut_off_at].to_vec()),
+                FailingReader,
+            )),
+            None => Box::new(std::io::Cursor::new(contents)),
+        };
+        Ok(RemoteBody::new(body).offset(offset))
+    }
+}
+
+struct FailingReader;
+
+impl std::io::Read for FailingReader {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::ConnectionReset.into())
+    }
+}
+
+#[test]
+fn test_remote_source() {
+    let source = Arc::new(MemorySource::default());
+    source.put("mem://data/hello.txt", "v1", b"Hello, World!");
+
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .max_backoff(0)
+        .remote_source("mem", source.clone())
+        .build()
+        .unwrap();
+
+    // Resources with the scheme are fetched through the source.
+    let path = cache.cached_path("mem://data/hello.txt").unwrap();
+    assert!(path.starts_with(cache_dir.path()));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "Hello, World!");
+    let meta = Meta::from_cache(&path).unwrap();
+    assert_eq!(meta.etag(), Some("v1"));
+    assert_eq!(meta.size(), Some(13));
+    assert_eq!(*source.fetches.lock().unwrap(), vec![0]);
+
+    // The version decides whether the cached copy is up-to-date.
+    assert_eq!(cache.cached_path("mem://data/hello.txt").unwrap(), path);
+    assert_eq!(source.fetches.lock().unwrap().len(), 1);
+    source.put("mem://data/hello.txt", "v2", b"Hello, everyone!");
+    let new_path = cache.cached_path("mem://data/hello.txt").unwrap();
+    assert_ne!(new_path, path);
+    assert_eq!(fs::read_to_string(&new_path).unwrap(), "Hello, everyone!");
+
+    // Errors from the source are passed through.
+    assert!(matches!(
+        cache.cached_path("mem://data/missing.txt"),
+        Err(Error::ResourceNotFound(_))
+    ));
+
+    // Downloads that get cut off are retried from where they left off.
+    source.put("mem://data/numbers.txt", "v1", b"0123456789");
+    *source.cut_off_at.lock().unwrap() = Some(4);
+    let path = cache
+        .cached_path_with_options(
+            "mem://data/numbers.txt",
+            &Options::default().checksum(&format!("sha256:{:x}", Sha256::digest(b"0123456789"))),
+        )
+        .unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "0123456789");
+    assert_eq!(source.fetches.lock().unwrap()[2..], [0, 4]);
+
+    // Sources that don't support conditional requests fall back to checking the version.
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .conditional_get(true)
+        .remote_source("MEM", source.clone())
+        .build()
+        .unwrap();
+    let metadata_calls = source
+        .metadata_calls
+        .load(std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(cache.cached_path("mem://data/hello.txt").unwrap(), new_path);
+    assert_eq!(
+        source
+            .metadata_calls
+            .load(std::sync::atomic::Ordering::SeqCst),
+        metadata_calls + 1
+    );
+
+    // Unregistered schemes are errors.
+    assert!(matches!(
+        cache.cached_path("other://data/hello.txt"),
+        Err(Error::UnsupportedScheme(scheme)) if scheme == "other"
+    ));
+}
+
+#[test]
+fn test_remote_source_replaces_http() {
+    let server = MockServer::start();
+    let fixture = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+    let source = Arc::new(MemorySource::default());
+    source.put(&fixture.url, "v1", b"Not from the server");
+
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .remote_source("http", source)
+        .build()
+        .unwrap();
+    let path = cache.cached_path(&fixture.url).unwrap();
+    assert_eq!(fs::read_to_string(path).unwrap(), "Not from the server");
+    assert_eq!(fixture.head.hits(), 0);
+    assert_eq!(fixture.get.hits(), 0);
+}
+
+/// Build a cache in `dir` that fetches resources with the given scheme through `source`.
+fn cache_with_source(dir: PathBuf, scheme: &str, source: Arc<dyn RemoteSource>) -> Cache {
+    Cache::builder()
+        .dir(dir)
+        .progress_bar(None)
+        .remote_source(scheme, source)
+        .build()
+        .unwrap()
+}
+
+/// Check that `resource` is downloaded once with the given contents and version, and isn't
+/// downloaded again while that version is current. Returns the cached path.
+fn assert_cached_once(
+    cached_path: impl Fn(&str) -> Result<PathBuf, Error>,
+    resource: &str,
+    contents: &str,
+    version: &str,
+    metadata: &MockRef,
+    download: &MockRef,
+) -> PathBuf {
+    let (metadata_hits, download_hits) = (metadata.hits(), download.hits());
+    let path = cached_path(resource).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+    assert_eq!(Meta::from_cache(&path).unwrap().etag(), Some(version));
+    assert_eq!(metadata.hits(), metadata_hits + 1);
+    assert_eq!(download.hits(), download_hits + 1);
+
+    assert_eq!(cached_path(resource).unwrap(), path);
+    assert_eq!(metadata.hits(), metadata_hits + 2);
+    assert_eq!(download.hits(), download_hits + 1);
+    path
+}
+
+/// Check that a resource that doesn't exist is a 404 error.
+fn assert_missing(cached_path: impl Fn(&str) -> Result<PathBuf, Error>, resource: &str) {
+    let err = cached_path(resource).unwrap_err();
+    assert_eq!(err.status_code(), Some(404));
+}
+
+/// Match requests to S3 that are signed with a session token.
+fn s3_signed(when: httpmock::When) -> httpmock::When {
+    when.header_exists("authorization")
+        .header_exists("x-amz-date")
+        .header(
+            "x-amz-content-sha256",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        )
+        .header("x-amz-security-token", "session-token")
+}
+
+/// Mock an object at `s3://bucket/models/model.bin`. Returns the mocks for the HEAD and
+/// GET requests.
+fn mock_s3_object(server: &MockServer) -> (MockRef<'_>, MockRef<'_>) {
+    let head = server.mock(|when, then| {
+        s3_signed(when.method(HEAD).path("/bucket/models/model.bin"));
+        then.status(200).header(ETAG_KEY, "\"abc\"");
+    });
+    let get = server.mock(|when, then| {
+        s3_signed(when.method(GET).path("/bucket/models/model.bin"));
+        then.status(200)
+            .header(ETAG_KEY, "\"abc\"")
+            .body("model weights");
+    });
+    (head, get)
+}
+
+/// Mock a file at `hf://org/repo/config.json` that the `main` revision resolves to
+/// `commit` for, with requests authenticated by the token `hf-token`. Returns the mocks for
+/// the HEAD and GET requests.
+fn mock_hf_file<'a>(hub: &'a MockServer, commit: &str) -> (MockRef<'a>, MockRef<'a>) {
+    let head = hub.mock(|when, then| {
+        when.method(HEAD)
+            .path("/org/repo/resolve/main/config.json")
+            .header("authorization", "Bearer hf-token");
+        then.status(200)
+            .header(ETAG_KEY, "\"config-etag\"")
+            .header("x-repo-commit", commit);
+    });
+    let get = hub.mock(|when, then| {
+        when.method(GET)
+            .path(format!("/org/repo/resolve/{}/config.json", commit))
+            .header("authorization", "Bearer hf-token");
+        then.status(200).body("{\"hidden_size\": 8}");
+    });
+    (head, get)
+}
+
+/// Mock an object at `gs://bucket/models/model.bin`, with requests authenticated by the
+/// access token `ya29.token`. Returns the mocks for the metadata and media requests.
+fn mock_gcs_object(server: &MockServer) -> (MockRef<'_>, MockRef<'_>) {
+    // Downloads are more specific, so they're matched first.
+    let media = server.mock(|when, then| {
+        when.method(GET)
+            .path("/storage/v1/b/bucket/o/models%2Fmodel.bin")
+            .query_param("alt", "media")
+            .query_param("generation", "1700000000000001")
+            .header("authorization", "Bearer ya29.token");
+        then.status(200).body("model weights");
+    });
+    let metadata = server.mock(|when, then| {
+        when.method(GET)
+            .path("/storage/v1/b/bucket/o/models%2Fmodel.bin")
+            .header("authorization", "Bearer ya29.token");
+        then.status(200).body(
+            "{\"name\": \"models/model.bin\", \"generation\": \"1700000000000001\", \
+             \"size\": \"13\", \"contentType\": \"application/octet-stream\"}",
+        );
+    });
+    (metadata, media)
+}
+
+#[test]
+fn test_s3_source() {
+    let server = MockServer::start();
+    let (head, get) = mock_s3_object(&server);
+    let versioned_head = server.mock(|when, then| {
+        s3_signed(when.method(HEAD).path("/bucket/versioned.txt"));
+        then.status(200)
+            .header(ETAG_KEY, "\"def\"")
+            .header("x-amz-version-id", "v123");
+    });
+    let versioned_get = server.mock(|when, then| {
+        s3_signed(when.method(GET).path("/bucket/versioned.txt")).query_param("versionId", "v123");
+        then.status(200)
+            .header(ETAG_KEY, "\"def\"")
+            .body("version 123");
+    });
+
+    // Credentials are read from the profile in the credentials file.
+    let dir = tempdir().unwrap();
+    let credentials_file = dir.path().join("credentials");
+    fs::write(
+        &credentials_file,
+        "[default]\naws_access_key_id = wrong\naws_secret_access_key = wrong\n\n\
+         [test]\naws_access_key_id = AKID\naws_secret_access_key = secret\n\
+         aws_session_token = session-token\n",
+    )
+    .unwrap();
+    let s3 = crate::S3Source::new()
+        .endpoint(&server.url(""))
+        .region("us-west-2")
+        .credentials_file(credentials_file)
+        .profile("test");
+    let cache = cache_with_source(dir.path().join("cache"), "s3", Arc::new(s3));
+
+    // The same version isn't downloaded again.
+    let path = assert_cached_once(
+        |resource| cache.cached_path(resource),
+        "s3://bucket/models/model.bin",
+        "model weights",
+        "\"abc\"",
+        &head,
+        &get,
+    );
+    assert_eq!(
+        path,
+        cache.resource_to_filepath(
+            "s3://bucket/models/model.bin",
+            &Some("\"abc\"".into()),
+            None,
+            None
+        )
+    );
+
+    // The version ID of objects in versioned buckets is used as the version, and that
+    // exact version is downloaded.
+    let path = cache.cached_path("s3://bucket/versioned.txt").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "version 123");
+    assert_eq!(versioned_head.hits(), 1);
+    assert_eq!(versioned_get.hits(), 1);
+    assert_eq!(Meta::from_cache(&path).unwrap().etag(), Some("v123"));
+
+    // Servers that send unquoted ETAGs don't have them mistaken for version IDs,
+    // including when resuming a download.
+    let unquoted_head = server.mock(|when, then| {
+        s3_signed(when.method(HEAD).path("/bucket/unquoted.txt"));
+        then.status(200).header(ETAG_KEY, "abc123");
+    });
+    let unquoted_by_version_id = server.mock(|when, then| {
+        s3_signed(when.method(GET).path("/bucket/unquoted.txt")).query_param_exists("versionId");
+        then.status(404);
+    });
+    let unquoted_get = server.mock(|when, then| {
+        s3_signed(when.method(GET).path("/bucket/unquoted.txt"))
+            .header("range", "bytes=8-")
+            .header("if-match", "abc123");
+        then.status(206).header(ETAG_KEY, "abc123").body("ETAG");
+    });
+    let path = cache.resource_to_filepath(
+        "s3://bucket/unquoted.txt",
+        &Some("abc123".into()),
+        None,
+        None,
+    );
+    fs::write(Cache::partial_path(&path), "unquoted").unwrap();
+    assert_eq!(cache.cached_path("s3://bucket/unquoted.txt").unwrap(), path);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "unquotedETAG");
+    assert_eq!(unquoted_head.hits(), 1);
+    assert_eq!(unquoted_by_version_id.hits(), 0);
+    assert_eq!(unquoted_get.hits(), 1);
+
+    // Missing objects are errors.
+    assert_missing(
+        |resource| cache.cached_path(resource),
+        "s3://bucket/missing.txt",
+    );
+    assert!(matches!(
+        cache.cached_path("s3://bucket"),
+        Err(Error::InvalidUrl(_))
+    ));
+}
+
+#[test]
+fn test_hf_source() {
+    let hub = MockServer::start();
+    let cdn = MockServer::start();
+    let commit = "0123456789abcdef0123456789abcdef01234567";
+    let lfs_commit = "89abcdef0123456789abcdef0123456789abcdef";
+    let new_lfs_commit = "fedcba9876543210fedcba9876543210fedcba98";
+
+    let (head, get) = mock_hf_file(&hub, commit);
+    // LFS files redirect to storage elsewhere, and the redirect describes the file.
+    let mock_lfs_file = |commit: &str| {
+        let head = hub.mock(|when, then| {
+            when.method(HEAD)
+                .path("/org/repo/resolve/v1.0/model.bin")
+                .header("authorization", "Bearer hf-token");
+            then.status(302)
+                .header("location", &cdn.url("/model.bin"))
+                .header(ETAG_KEY, "\"redirect-etag\"")
+                .header("x-linked-etag", "\"model-sha256\"")
+                .header("x-linked-size", "13")
+                .header("x-repo-commit", commit);
+        });
+        let get = hub.mock(|when, then| {
+            when.method(GET)
+                .path(format!("/org/repo/resolve/{}/model.bin", commit))
+                .header("authorization", "Bearer hf-token");
+            then.status(302).header("location", &cdn.url("/model.bin"));
+        });
+        (head, get)
+    };
+    let (mut lfs_head, lfs_get) = mock_lfs_file(lfs_commit);
+    let cdn_authorized = cdn.mock(|when, then| {
+        when.method(GET)
+            .path("/model.bin")
+            .header_exists("authorization");
+        then.status(400);
+    });
+    let cdn_get = cdn.mock(|when, then| {
+        when.method(GET).path("/model.bin");
+        then.status(200).body("model weights");
+    });
+
+    let dir = tempdir().unwrap();
+    let hf = crate::HfSource::new()
+        .unwrap()
+        .endpoint(&hub.url(""))
+        .token("hf-token");
+    let cache = cache_with_source(dir.path().to_owned(), "hf", Arc::new(hf));
+
+    // The commit that the revision resolved to is the version, and the same commit isn't
+    // downloaded again.
+    let path = assert_cached_once(
+        |resource| cache.cached_path(resource),
+        "hf://org/repo/config.json",
+        "{\"hidden_size\": 8}",
+        commit,
+        &head,
+        &get,
+    );
+    assert_eq!(
+        path,
+        cache.resource_to_filepath(
+            "hf://org/repo/config.json",
+            &Some(commit.into()),
+            None,
+            None
+        )
+    );
+
+    // LFS files are versioned by the hash of their contents, but still downloaded from
+    // the commit. The token isn't sent to the storage that they're downloaded from.
+    let path = cache.cached_path("hf://org/repo@v1.0/model.bin").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "model weights");
+    assert_eq!(lfs_head.hits(), 1);
+    assert_eq!(lfs_get.hits(), 1);
+    assert_eq!(cdn_authorized.hits(), 0);
+    assert_eq!(cdn_get.hits(), 1);
+    assert_eq!(
+        Meta::from_cache(&path).unwrap().etag(),
+        Some("\"model-sha256\"")
+    );
+
+    // So new commits that don't change them don't cause them to be downloaded again.
+    lfs_head.delete();
+    let (new_lfs_head, new_lfs_get) = mock_lfs_file(new_lfs_commit);
+    assert_eq!(
+        cache.cached_path("hf://org/repo@v1.0/model.bin").unwrap(),
+        path
+    );
+    assert_eq!(new_lfs_head.hits(), 1);
+    assert_eq!(new_lfs_get.hits(), 0);
+    assert_eq!(cdn_get.hits(), 1);
+
+    // Missing files are errors.
+    assert_missing(
+        |resource| cache.cached_path(resource),
+        "hf://org/repo/missing.txt",
+    );
+    assert!(matches!(
+        cache.cached_path("hf://org/repo"),
+        Err(Error::InvalidUrl(_))
+    ));
+}
+
+#[test]
+fn test_gcs_source() {
+    let server = MockServer::start();
+    let token = server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/token")
+            .body_contains("grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Ajwt-bearer")
+            .body_contains("assertion=");
+        then.status(200)
+            .body("{\"access_token\": \"ya29.token\", \"expires_in\": 3600}");
+    });
+    let (metadata, media) = mock_gcs_object(&server);
+
+    // The service account key is exchanged for an access token.
+    let dir = tempdir().unwrap();
+    let key_file = dir.path().join("service-account.json");
+    fs::write(
+        &key_file,
+        serde_json::json!({
+            "type": "service_account",
+            "client_email": "reader@project.iam.gserviceaccount.com",
+            "private_key_id": "key-id",
+            "private_key": fs::read_to_string("test_fixtures/service-account-key.pem").unwrap(),
+            "token_uri": server.url("/token"),
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let gcs = crate::GcsSource::new()
+        .endpoint(&server.url(""))
+        .service_account_file(key_file.clone());
+    let cache = cache_with_source(dir.path().join("cache"), "gs", Arc::new(gcs));
+
+    // The generation is the version, and that exact generation is downloaded. The same
+    // generation isn't downloaded again, and the access token is reused.
+    let path = assert_cached_once(
+        |resource| cache.cached_path(resource),
+        "gs://bucket/models/model.bin",
+        "model weights",
+        "1700000000000001",
+        &metadata,
+        &media,
+    );
+    assert_eq!(
+        path,
+        cache.resource_to_filepath(
+            "gs://bucket/models/model.bin",
+            &Some("1700000000000001".into()),
+            None,
+            None
+        )
+    );
+    assert_eq!(token.hits(), 1);
+
+    // Access tokens can be given directly.
+    let gcs = crate::GcsSource::new()
+        .endpoint(&server.url(""))
+        .access_token("ya29.token");
+    let cache = cache_with_source(dir.path().join("other-cache"), "gs", Arc::new(gcs));
+    let path = cache.cached_path("gs://bucket/models/model.bin").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "model weights");
+    assert_eq!(token.hits(), 1);
+
+    // Missing objects and invalid keys are errors.
+    assert_missing(
+        |resource| cache.cached_path(resource),
+        "gs://bucket/missing.txt",
+    );
+    fs::write(&key_file, "{\"type\": \"authorized_user\"}").unwrap();
+    let gcs = crate::GcsSource::new()
+        .endpoint(&server.url(""))
+        .service_account_file(key_file);
+    let cache = cache_with_source(dir.path().join("other-cache"), "gs", Arc::new(gcs));
+    assert!(matches!(
+        cache.cached_path("gs://bucket/models/model.bin"),
+        Err(Error::InvalidCredentials(_))
+    ));
+}
+
+#[test]
+fn test_relocated_cache() {
+    let server = MockServer::start();
+
+    let root = tempdir().unwrap();
+    let cache_dir = root.path().join("cache");
+    let cache = Cache::builder()
+        .dir(cache_dir.clone())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+    let fixture = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+    let path = cache
+        .cached_path_with_options(&fixture.url, &Options::default().subdir("target"))
+        .unwrap();
+
+    // The meta file doesn't reference the cache directory.
+    let serialized = fs::read_to_string(Meta::meta_path_for(&path)).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(value["version"], 2);
+    assert_eq!(
+        value["resource_path"],
+        path.file_name().unwrap().to_str().unwrap()
+    );
+
+    // Move the whole cache directory somewhere else.
+    let moved_dir = root.path().join("moved");
+    fs::rename(&cache_dir, &moved_dir).unwrap();
+    let cache = Cache::builder()
+        .dir(moved_dir.clone())
+        .progress_bar(None)
+        .offline(true)
+        .build()
+        .unwrap();
+    let moved_path = cache
+        .cached_path_with_options(&fixture.url, &Options::default().subdir("target"))
+        .unwrap();
+    assert_eq!(
+        moved_path,
+        moved_dir.join("target").join(path.file_name().unwrap())
+    );
+    let meta = cache.entries().next().unwrap();
+    assert_eq!(meta.resource_path(), moved_path);
+    assert_eq!(meta.meta_path(), Meta::meta_path_for(&moved_path));
+
+    // Meta files from older versions with stale absolute paths are migrated.
+    let legacy = serde_json::json!({
+        "resource": fixture.url,
+        "resource_path": path,
+        "meta_path": Meta::meta_path_for(&path),
+        "etag": "fake-etag",
+        "expires": null,
+        "creation_time": 1_600_000_000.0,
+    });
+    fs::write(meta.meta_path(), legacy.to_string()).unwrap();
+    let migrated = Meta::from_cache(&moved_path).unwrap();
+    assert_eq!(migrated.resource_path(), moved_path);
+    assert_eq!(migrated.meta_path(), meta.meta_path());
+    assert_eq!(migrated.etag(), Some("fake-etag"));
+    assert_eq!(migrated.version, crate::meta::META_VERSION);
+    assert_eq!(
+        cache
+            .cached_path_with_options(&fixture.url, &Options::default().subdir("target"))
+            .unwrap(),
+        moved_path
+    );
+}
+
+#[test]
+fn test_export_import() {
+    let server = MockServer::start();
+
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    let hello = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+    let sample = Fixture::load(
+        &server,
+        "test_fixtures/utf-8_sample/utf-8_sample.txt",
+        "fake-etag",
+    );
+    let checksum = format!(
+        "sha256:{:x}",
+        Sha256::digest(fs::read("test_fixtures/hello.txt").unwrap())
+    );
+    cache
+        .cached_path_with_options(&hello.url, &Options::default().checksum(&checksum))
+        .unwrap();
+    cache
+        .cached_path_with_options(&sample.url, &Options::default().subdir("target"))
+        .unwrap();
+
+    let mut bundle: Vec<u8> = vec![];
+    let exported = cache.export(&[], &mut bundle).unwrap();
+    assert_eq!(exported.len(), 2);
+    let mut single_bundle: Vec<u8> = vec![];
+    let exported = cache.export(&[&sample.url], &mut single_bundle).unwrap();
+    assert_eq!(exported.len(), 1);
+    assert!(matches!(
+        cache.export(&[&server.url("/missing.txt")], &mut vec![]),
+        Err(Error::NoCachedVersions(_))
+    ));
+
+    // Import into a cache somewhere else, which can then be used offline.
+    let other_cache_dir = tempdir().unwrap();
+    let other_cache = Cache::builder()
+        .dir(other_cache_dir.path().to_owned())
+        .progress_bar(None)
+        .offline(true)
+        .build()
+        .unwrap();
+    let imported = other_cache.import(&bundle[..]).unwrap();
+    assert_eq!(imported.len(), 2);
+    for meta in other_cache.entries() {
+        assert!(meta.resource_path().starts_with(other_cache_dir.path()));
+        assert!(meta.meta_path().starts_with(other_cache_dir.path()));
+        other_cache.verify(&meta).unwrap();
+    }
+    let path = other_cache
+        .cached_path_with_options(&sample.url, &Options::default().subdir("target"))
+        .unwrap();
+    assert!(path.starts_with(other_cache_dir.path().join("target")));
+    assert_eq!(
+        fs::read(&path).unwrap(),
+        fs::read("test_fixtures/utf-8_sample/utf-8_sample.txt").unwrap()
+    );
+
+    // Importing the same bundle again doesn't change anything.
+    assert!(other_cache.import(&bundle[..]).unwrap().is_empty());
+
+    // Bundles with files that don't match their meta are rejected.
+    let meta = cache.entries().next().unwrap();
+    let relative_path = meta.resource_path().strip_prefix(cache_dir.path()).unwrap();
+    let mut builder = tar::Builder::new(vec![]);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(6);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, relative_path, &b"Hello!"[..])
+        .unwrap();
+    builder
+        .append_path_with_name(meta.meta_path(), Meta::meta_path_for(relative_path))
+        .unwrap();
+    let tampered = builder.into_inner().unwrap();
+    let third_cache_dir = tempdir().unwrap();
+    let third_cache = Cache::builder()
+        .dir(third_cache_dir.path().to_owned())
+        .build()
+        .unwrap();
+    assert!(matches!(
+        third_cache.import(&tampered[..]),
+        Err(Error::InvalidBundle(_)) | Err(Error::ChecksumMismatch { .. })
+    ));
+    assert_eq!(third_cache.entries().count(), 0);
+
+    // As are bundles with entries outside of the cache.
+    let mut builder = tar::Builder::new(vec![]);
+    append_raw_tar_entry(
+        &mut builder,
+        "../evil.meta",
+        tar::EntryType::Regular,
+        None,
+        b"{}",
+    );
+    let evil = builder.into_inner().unwrap();
+    assert!(matches!(
+        third_cache.import(&evil[..]),
+        Err(Error::ExtractionError(
+            ExtractionErrorKind::PolicyViolation(_)
+        ))
+    ));
+}
+
+#[test]
+fn test_max_size_evicts_least_recently_used() {
+    let server = MockServer::start();
+
+    // Setup cache. The limit fits both fixtures, but not a third resource.
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .freshness_lifetime(300)
+        .max_size(14_100)
+        .build()
+        .unwrap();
+
+    // Mock the resources.
+    let hello = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+    let sample = Fixture::load(
+        &server,
+        "test_fixtures/utf-8_sample/utf-8_sample.txt",
+        "fake-etag",
+    );
+    let _big_get = server.mock(|when, then| {
+        when.method(GET).path("/big.txt");
+        then.status(200)
+            .header(ETAG_KEY, "fake-etag")
+            .body("x".repeat(50));
+    });
+    let _big_head = server.mock(|when, then| {
+        when.method(HEAD).path("/big.txt");
+        then.status(200).header(ETAG_KEY, "fake-etag");
+    });
+
+    let hello_path = cache.cached_path(&hello.url).unwrap();
+    let sample_path = cache.cached_path(&sample.url).unwrap();
+
+    // Access the first resource again so that the second is the least recently used.
+    assert_eq!(cache.cached_path(&hello.url).unwrap(), hello_path);
+    assert!(Meta::from_cache(&hello_path).unwrap().last_access() > 0.0);
+
+    let big_path = cache.cached_path(&server.url("/big.txt")).unwrap();
+
+    assert!(big_path.is_file());
+    assert!(hello_path.is_file());
+    assert!(!sample_path.exists());
+    assert!(!Meta::meta_path_for(&sample_path).exists());
+    assert!(Path::new(&format!("{}.lock", sample_path.to_str().unwrap())).exists());
+    assert_eq!(cache.entries().count(), 2);
+}
+
+#[test]
+fn test_record_access() {
+    let server = MockServer::start();
+    let hello = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+
+    // Without a max size the meta isn't rewritten on every access.
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .freshness_lifetime(300)
+        .build()
+        .unwrap();
+    let path = cache.cached_path(&hello.url).unwrap();
+    let meta_path = Meta::meta_path_for(&path);
+    let serialized = fs::read_to_string(&meta_path).unwrap();
+    assert_eq!(cache.cached_path(&hello.url).unwrap(), path);
+    assert_eq!(fs::read_to_string(&meta_path).unwrap(), serialized);
+
+    // With a max size it is.
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .freshness_lifetime(300)
+        .max_size(1_000_000)
+        .build()
+        .unwrap();
+    assert_eq!(cache.cached_path(&hello.url).unwrap(), path);
+    let meta = Meta::from_cache(&path).unwrap();
+    assert!(meta.last_access() > meta.creation_time());
+
+    // An access to a resource that was evicted in the meantime doesn't leave a meta behind.
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&meta_path).unwrap();
+    assert!(cache.record_access(meta).unwrap().is_none());
+    assert!(!meta_path.exists());
+    assert_eq!(hello.get.hits(), 1);
+}
+
+#[test]
+fn test_max_size_skips_locked_resources() {
+    let server = MockServer::start();
+
+    // Setup cache with a limit that always requires eviction.
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .max_size(0)
+        .build()
+        .unwrap();
+
+    // Mock the resources.
+    let hello = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+    let sample = Fixture::load(
+        &server,
+        "test_fixtures/utf-8_sample/utf-8_sample.txt",
+        "fake-etag",
+    );
+
+    // The resource that was just downloaded is kept even though it's over the limit.
+    let hello_path = cache.cached_path(&hello.url).unwrap();
+    assert!(hello_path.is_file());
+
+    // Simulate another process holding the lock on the first resource.
+    let filelock = fs::File::open(format!("{}.lock", hello_path.to_str().unwrap())).unwrap();
+    filelock.lock_exclusive().unwrap();
+
+    let sample_path = cache.cached_path(&sample.url).unwrap();
+    assert!(hello_path.is_file());
+    assert!(sample_path.is_file());
+
+    // Once the lock is released the first resource can be evicted.
+    filelock.unlock().unwrap();
+    drop(filelock);
+    let extraction_path = cache
+        .cached_path_with_options(
+            "test_fixtures/utf-8_sample/archives/utf-8.tar.gz",
+            &Options::default().extract(),
+        )
+        .unwrap();
+    assert!(extraction_path.is_dir());
+    assert!(!hello_path.exists());
+    assert!(!sample_path.exists());
+}
+
+#[test]
+fn test_extract_tar_gz() {
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    let resource: PathBuf = [
+        ".",
+        "test_fixtures",
+        "utf-8_sample",
+        "archives",
+        "utf-8.tar.gz",
+    ]
+    .iter()
+    .collect();
+
+    let path = cache
+        .cached_path_with_options(resource.to_str().unwrap(), &Options::default().extract())
+        .unwrap();
+    assert!(path.is_dir());
+    assert!(path.to_str().unwrap().ends_with("-extracted"));
+    assert!(path
+        .to_str()
+        .unwrap()
+        .starts_with(cache_dir.path().to_str().unwrap()));
+    let sample_file_path = path.join("dummy.txt");
+    assert!(sample_file_path.is_file());
+}
+
+#[test]
+fn test_extract_zip() {
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    let resource: PathBuf = [
+        ".",
+        "test_fixtures",
+        "utf-8_sample",
+        "archives",
+        "utf-8.zip",
+    ]
+    .iter()
+    .collect();
+
+    let path = cache
+        .cached_path_with_options(resource.to_str().unwrap(), &Options::default().extract())
+        .unwrap();
+    assert!(path.is_dir());
+    assert!(path.to_str().unwrap().ends_with("-extracted"));
+    assert!(path
+        .to_str()
+        .unwrap()
+        .starts_with(cache_dir.path().to_str().unwrap()));
+    let sample_file_path = path.join("dummy.txt");
+    assert!(sample_file_path.is_file());
+}
+
+#[test]
+fn test_extract_tar_formats() {
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    for extension in ["tar", "tgz", "tar.bz2", "tar.xz", "tar.zst"].iter() {
+        let resource = format!("test_fixtures/utf-8_sample/archives/utf-8.{}", extension);
+        let path = cache
+            .cached_path_with_options(&resource, &Options::default().extract())
+            .unwrap();
+        assert!(path.is_dir());
+        assert!(path.join("dummy.txt").is_file());
+        assert!(path.join("folder").join("utf-8_sample.txt").is_file());
+    }
+}
+
+#[test]
+fn test_decompress_single_file() {
+    let server = MockServer::start();
+
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    for extension in ["gz", "xz", "zst"].iter() {
+        let resource = format!("test_fixtures/hello.txt.{}", extension);
+        let path = cache
+            .cached_path_with_options(&resource, &Options::default().extract())
+            .unwrap();
+        assert!(path.is_file());
+        assert!(path.to_str().unwrap().ends_with("-extracted"));
+        let contents = fs::read_to_string(&path).unwrap().replace("\r\n", "\n");
+        assert_eq!(&contents, "Hello, World!\n");
+    }
+
+    // Remote resources are decompressed next to the cached file.
+    let contents = fs::read("test_fixtures/hello.txt.gz").unwrap();
+    server.mock(|when, then| {
+        when.method(HEAD).path("/hello.txt.gz");
+        then.status(200).header(ETAG_KEY, "fake-etag");
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/hello.txt.gz");
+        then.status(200)
+            .header(ETAG_KEY, "fake-etag")
+            .body(&contents);
+    });
+    let resource = server.url("/hello.txt.gz");
+    let path = cache
+        .cached_path_with_options(&resource, &Options::default().extract())
+        .unwrap();
+    let meta = cache.entries().next().unwrap();
+    assert_eq!(path, meta.extraction_path());
+    assert!(path.is_file());
+
+    // And calling again doesn't decompress it again.
+    fs::write(&path, "Hello again!").unwrap();
+    let same_path = cache
+        .cached_path_with_options(&resource, &Options::default().extract())
+        .unwrap();
+    assert_eq!(same_path, path);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "Hello again!");
+}
+
+#[test]
+fn test_detect_archive_format() {
+    let server = MockServer::start();
+
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    // Mock an extensionless download endpoint.
+    let contents = fs::read("test_fixtures/utf-8_sample/archives/utf-8.tar.xz").unwrap();
+    server.mock(|when, then| {
+        when.method(HEAD).path("/download");
+        then.status(200)
+            .header(ETAG_KEY, "fake-etag")
+            .header("Content-Type", "application/octet-stream")
+            .header(
+                "Content-Disposition",
+                "attachment; filename=\"utf-8.tar.xz\"",
+            );
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/download");
+        then.status(200)
+            .header(ETAG_KEY, "fake-etag")
+            .body(&contents);
+    });
+    let resource = server.url("/download?id=1&format=.zip");
+
+    let path = cache
+        .cached_path_with_options(&resource, &Options::default().extract())
+        .unwrap();
+    assert!(path.is_dir());
+    assert!(path.join("dummy.txt").is_file());
+    let meta = cache.entries().next().unwrap();
+    assert_eq!(meta.filename(), Some("utf-8.tar.xz"));
+    assert_eq!(meta.content_type(), Some("application/octet-stream"));
+
+    // The format can also be forced, in which case the archive is only decompressed.
+    let resource = "test_fixtures/utf-8_sample/archives/utf-8.tar.gz";
+    let path = cache
+        .cached_path_with_options(
+            resource,
+            &Options::default()
+                .subdir("forced")
+                .extract()
+                .archive_format(ArchiveFormat::Gz),
+        )
+        .unwrap();
+    assert!(path.is_file());
+    assert_eq!(
+        fs::read(&path).unwrap(),
+        fs::read("test_fixtures/utf-8_sample/archives/utf-8.tar").unwrap()
+    );
+}
+
+#[test]
+fn test_extract_in_subdir() {
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    let resource: PathBuf = [
+        ".",
+        "test_fixtures",
+        "utf-8_sample",
+        "archives",
+        "utf-8.tar.gz",
+    ]
+    .iter()
+    .collect();
+
+    let path = cache
+        .cached_path_with_options(
+            resource.to_str().unwrap(),
+            &Options::default().subdir("target").extract(),
+        )
+        .unwrap();
+    assert!(path.is_dir());
+    assert!(path.to_str().unwrap().ends_with("-extracted"));
+    assert!(path.parent().unwrap().to_str().unwrap().ends_with("target"));
+    assert!(path
+        .to_str()
+        .unwrap()
+        .starts_with(cache_dir.path().to_str().unwrap()));
+    let sample_file_path = path.join("dummy.txt");
+    assert!(sample_file_path.is_file());
+}
+
+#[test]
+fn test_archive_member() {
+    let server = MockServer::start();
+
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    let contents = fs::read("test_fixtures/utf-8_sample/archives/utf-8.tar.gz").unwrap();
+    server.mock(|when, then| {
+        when.method(HEAD).path("/bundle.tar.gz");
+        then.status(200).header(ETAG_KEY, "fake-etag");
+    });
+    let get_mock = server.mock(|when, then| {
+        when.method(GET).path("/bundle.tar.gz");
+        then.status(200)
+            .header(ETAG_KEY, "fake-etag")
+            .body(&contents);
+    });
+    let resource = server.url("/bundle.tar.gz");
+
+    let path = cache
+        .cached_path(&format!("{}!/folder/utf-8_sample.txt", resource))
+        .unwrap();
+    assert!(path.is_file());
+    let meta = cache.entries().next().unwrap();
+    assert_eq!(
+        path,
+        meta.extraction_path()
+            .join("folder")
+            .join("utf-8_sample.txt")
+    );
+
+    // The archive is only downloaded and extracted once.
+    let path = cache
+        .cached_path(&format!("{}!/dummy.txt", resource))
+        .unwrap();
+    assert_eq!(path, meta.extraction_path().join("dummy.txt"));
+    get_mock.assert_hits(1);
+
+    // Members that don't exist or that point outside of the archive aren't found.
+    for member in ["missing.txt", "../bundle.tar.gz"].iter() {
+        let result = cache.cached_path(&format!("{}!/{}", resource, member));
+        assert!(matches!(result, Err(Error::ResourceNotFound(_))));
+    }
+
+    // Local archives work the same way.
+    let path = cache
+        .cached_path("test_fixtures/utf-8_sample/archives/utf-8.zip!/dummy.txt")
+        .unwrap();
+    assert!(path.is_file());
+    assert!(path.starts_with(cache_dir.path()));
+
+    // Resources that just have `!/` in them are used as they are.
+    let data_dir = tempdir().unwrap();
+    let file_path = data_dir.path().join("a!").join("b.txt");
+    fs::create_dir(data_dir.path().join("a!")).unwrap();
+    fs::write(&file_path, "Hello, World!").unwrap();
+    assert_eq!(
+        cache.cached_path(file_path.to_str().unwrap()).unwrap(),
+        file_path
+    );
+
+    let url_get = server.mock(|when, then| {
+        when.method(GET).path("/a!/b.txt");
+        then.status(200)
+            .header(ETAG_KEY, "fake-etag")
+            .body("Hello, World!");
+    });
+    server.mock(|when, then| {
+        when.method(HEAD).path("/a!/b.txt");
+        then.status(200).header(ETAG_KEY, "fake-etag");
+    });
+    let path = cache.cached_path(&server.url("/a!/b.txt")).unwrap();
+    assert_eq!(fs::read_to_string(path).unwrap(), "Hello, World!");
+    url_get.assert_hits(1);
+}
+
+#[test]
+fn test_extract_only() {
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+
+    for extension in ["tar.gz", "zip"].iter() {
+        let resource = format!("test_fixtures/utf-8_sample/archives/utf-8.{}", extension);
+        let full_path = cache
+            .cached_path_with_options(&resource, &Options::default().extract())
+            .unwrap();
+
+        // Only the matching members are extracted, to a separate directory.
+        let path = cache
+            .cached_path_with_options(&resource, &Options::default().extract_only(["folder/*"]))
+            .unwrap();
+        assert_ne!(path, full_path);
+        assert!(path.to_str().unwrap().contains("-extracted-"));
+        assert!(path.join("folder").join("utf-8_sample.txt").is_file());
+        assert!(!path.join("dummy.txt").exists());
+
+        // Wildcards don't match across directories.
+        let path = cache
+            .cached_path_with_options(&resource, &Options::default().extract_only(["*.txt"]))
+            .unwrap();
+        assert!(path.join("dummy.txt").is_file());
+        assert!(!path.join("folder").join("utf-8_sample.txt").exists());
+
+        // The same set of patterns maps to the same directory, regardless of the order.
+        let path = cache
+            .cached_path_with_options(
+                &resource,
+                &Options::default().extract_only(vec!["**/*.txt", "dummy.txt"]),
+            )
+            .unwrap();
+        let same_path = cache
+            .cached_path_with_options(
+                &resource,
+                &Options::default().extract_only(vec!["dummy.txt", "**/*.txt"]),
+            )
+            .unwrap();
+        assert_eq!(path, same_path);
+        assert!(path.join("dummy.txt").is_file());
+        assert!(path.join("folder").join("utf-8_sample.txt").is_file());
+    }
+
+    let result = cache.cached_path_with_options(
+        "test_fixtures/utf-8_sample/archives/utf-8.zip",
+        &Options::default().extract_only(["[folder"]),
+    );
+    assert!(matches!(result, Err(Error::InvalidGlob(_))));
+}
+
+#[test]
+fn test_extract_only_old_versions_removed() {
+    let server = MockServer::start();
+
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .keep_versions(1)
+        .build()
+        .unwrap();
+
+    let contents = fs::read("test_fixtures/utf-8_sample/archives/utf-8.tar.gz").unwrap();
+    let resource = server.url("/utf-8.tar.gz");
+    let mut paths: Vec<PathBuf> = vec![];
+    for etag in &["fake-etag-1", "fake-etag-2"] {
+        let mut head = server.mock(|when, then| {
+            when.method(HEAD).path("/utf-8.tar.gz");
+            then.status(200).header(ETAG_KEY, *etag);
+        });
+        let mut get = server.mock(|when, then| {
+            when.method(GET).path("/utf-8.tar.gz");
+            then.status(200).header(ETAG_KEY, *etag).body(&contents);
+        });
+        paths.push(
+            cache
+                .cached_path_with_options(&resource, &Options::default().extract())
+                .unwrap(),
+        );
+        paths.push(
+            cache
+                .cached_path_with_options(&resource, &Options::default().extract_only(["*.txt"]))
+                .unwrap(),
+        );
+        head.delete();
+        get.delete();
+    }
+
+    // Every extraction directory of the old version is removed along with it.
+    assert!(!paths[0].exists());
+    assert!(!paths[1].exists());
+    assert!(Path::new(&format!("{}.lock", paths[1].to_str().unwrap())).exists());
+    assert!(paths[2].is_dir());
+    assert!(paths[3].is_dir());
+    assert_eq!(cache.entries().count(), 1);
+}
+
+/// Write a tar entry without any of the validation done by `tar::Builder`.
+fn append_raw_tar_entry(
+    builder: &mut tar::Builder<impl std::io::Write>,
+    name: &str,
+    entry_type: tar::EntryType,
+    link_name: Option<&str>,
+    contents: &[u8],
+) {
+    let mut header = tar::Header::new_old();
+    header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+    if let Some(link_name) = link_name {
+        header.as_old_mut().linkname[..link_name.len()].copy_from_slice(link_name.as_bytes());
+    }
+    header.set_entry_type(entry_type);
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append(&header, contents).unwrap();
+}
+
+#[test]
+fn test_extraction_policy() {
+    use crate::{ExtractionPolicy, LinkPolicy, PolicyViolation};
+    use std::io::Write;
+    use tar::EntryType;
+
+    let cache_dir = tempdir().unwrap();
+    let cache = Cache::builder()
+        .dir(cache_dir.path().to_owned())
+        .progress_bar(None)
+        .build()
+        .unwrap();
+    let archives_dir = tempdir().unwrap();
+
+    let write_tar = |name: &str, entries: &[(&str, EntryType, Option<&str>)]| {
+        let path = archives_dir.path().join(name);
+        let mut builder = tar::Builder::new(fs::File::create(&path).unwrap());
+        for (entry_name, entry_type, link_name) in entries {
+            let contents: &[u8] = if *entry_type == EntryType::Regular {
+                b"Hello, World!"
+            } else {
+                b""
+            };
+            append_raw_tar_entry(&mut builder, entry_name, *entry_type, *link_name, contents);
+        }
+        builder.finish().unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    let extract = |resource: &str, policy: ExtractionPolicy| {
+        cache.cached_path_with_options(
+            resource,
+            &Options::default().extract().extraction_policy(policy),
+        )
+    };
+    let violation = |result: Result<PathBuf, Error>| match result {
+        Err(Error::ExtractionError(ExtractionErrorKind::PolicyViolation(violation))) => violation,
+        other => panic!("expected a policy violation, got {:?}", other),
+    };
+
+    // Entries can't escape the extraction directory through their path.
+    let resource = write_tar("parent.tar", &[("../evil.txt", EntryType::Regular, None)]);
+    assert_eq!(
+        violation(extract(&resource, ExtractionPolicy::default())),
+        PolicyViolation::ParentDirectory("../evil.txt".into())
+    );
+    assert!(!cache_dir.path().join("evil.txt").exists());
+
+    let resource = write_tar("absolute.tar", &[("/evil.txt", EntryType::Regular, None)]);
+    assert_eq!(
+        violation(extract(&resource, ExtractionPolicy::default())),
+        PolicyViolation::AbsolutePath("/evil.txt".into())
+    );
+
+    // Or through links.
+    let resource = write_tar(
+        "symlink.tar",
+        &[("link", EntryType::Symlink, Some("../../etc/passwd"))],
+    );
+    assert!(matches!(
+        violation(extract(&resource, ExtractionPolicy::default())),
+        PolicyViolation::LinkOutsideTarget { .. }
+    ));
+
+    let resource = write_tar(
+        "hardlink.tar",
+        &[("link", EntryType::Link, Some("/etc/passwd"))],
+    );
+    assert!(matches!(
+        violation(extract(&resource, ExtractionPolicy::default())),
+        PolicyViolation::LinkOutsideTarget { .. }
+    ));
+
+    // Including chains of links that each look fine on their own.
+    let resource = write_tar(
+        "chain.tar",
+        &[
+            ("dir", EntryType::Symlink, Some(".")),
+            ("dir/link", EntryType::Symlink, Some("..")),
+        ],
+    );
+    assert!(matches!(
+        violation(extract(&resource, ExtractionPolicy::default())),
+        PolicyViolation::LinkOutsideTarget { .. }
+    ));
+
+    // Links inside of the extraction directory are fine, unless links are rejected.
+    let resource = write_tar(
+        "internal.tar",
+        &[
+            ("hello.txt", EntryType::Regular, None),
+            ("link.txt", EntryType::Symlink, Some("hello.txt")),
+        ],
+    );
+    assert_eq!(
+        violation(extract(
+            &resource,
+            ExtractionPolicy::default().links(LinkPolicy::Reject)
+        )),
+        PolicyViolation::Link("link.txt".into())
+    );
+    let path = extract(&resource, ExtractionPolicy::default()).unwrap();
+    assert_eq!(
+        fs::read_to_string(path.join("link.txt")).unwrap(),
+        "Hello, World!"
+    );
+
+    // Limits on the number of entries and the size.
+    let resource = write_tar(
+        "entries.tar",
+        &[
+            ("a.txt", EntryType::Regular, None),
+            ("b.txt", EntryType::Regular, None),
+        ],
+    );
+    assert_eq!(
+        violation(extract(
+            &resource,
+            ExtractionPolicy::default().max_entries(1)
+        )),
+        PolicyViolation::EntryCount(1)
+    );
+    assert_eq!(
+        violation(extract(
+            &resource,
+            ExtractionPolicy::default().max_total_size(1024)
+        )),
+        PolicyViolation::TotalSize(1024)
+    );
+
+    // And on the compression ratio, for all kinds of archives.
+    let bomb_path = archives_dir.path().join("bomb.tar.gz");
+    let mut encoder = flate2::write::GzEncoder::new(
+        fs::File::create(&bomb_path).unwrap(),
+        flate2::Compression::best(),
+    );
+    let mut builder = tar::Builder::new(&mut encoder);
+    append_raw_tar_entry(
+        &mut builder,
+        "zeros",
+        EntryType::Regular,
+        None,
+        &vec![0; 10 * 1024 * 1024],
+    );
+    builder.finish().unwrap();
+    drop(builder);
+    encoder.finish().unwrap();
+    assert_eq!(
+        violation(extract(
+            bomb_path.to_str().unwrap(),
+            ExtractionPolicy::default().max_compression_ratio(100.0)
+        )),
+        PolicyViolation::CompressionRatio(100.0)
+    );
+
+    let zip_path = archives_dir.path().join("bomb.zip");
+    let mut writer = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+    writer
+        .start_file("zeros", zip::write::FileOptions::default())
+        .unwrap();
+    writer.write_all(&vec![0; 10 * 1024 * 1024]).unwrap();
+    writer
+        .start_file("../evil.txt", zip::write::FileOptions::default())
+        .unwrap();
+    writer.finish().unwrap();
+    assert_eq!(
+        violation(extract(
+            zip_path.to_str().unwrap(),
+            ExtractionPolicy::default().max_compression_ratio(100.0)
+        )),
+        PolicyViolation::CompressionRatio(100.0)
+    );
+    assert_eq!(
+        violation(extract(
+            zip_path.to_str().unwrap(),
+            ExtractionPolicy::default()
+        )),
+        PolicyViolation::ParentDirectory("../evil.txt".into())
+    );
+
+    // The compressed sizes in zip headers aren't trusted.
+    let zip_path = archives_dir.path().join("forged.zip");
+    let mut writer = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+    writer
+        .start_file("zeros", zip::write::FileOptions::default())
+        .unwrap();
+    writer.write_all(&vec![0; 10 * 1024 * 1024]).unwrap();
+    writer.finish().unwrap();
+    let mut contents = fs::read(&zip_path).unwrap();
+    let header = contents
+        .windows(4)
+        .position(|window| window == b"PK\x01\x02")
+        .unwrap();
+    contents[header + 20..header + 24].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+    fs::write(&zip_path, contents).unwrap();
+    assert_eq!(
+        violation(extract(
+            zip_path.to_str().unwrap(),
+            ExtractionPolicy::default().max_compression_ratio(100.0)
+        )),
+        PolicyViolation::CompressionRatio(100.0)
+    );
+
+    // Nothing is left behind by failed extractions, only the one that succeeded remains.
+    let extracted = fs::read_dir(cache_dir.path())
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().is_dir())
+        .count();
+    assert_eq!(extracted, 1);
+}
+
+#[cfg(feature = "async")]
+mod async_cache {
+    use super::*;
+    use crate::AsyncCache;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cached_path_remote_file() {
+        let server = MockServer::start();
+        let runtime = runtime();
+
+        // Setup cache.
+        let cache_dir = tempdir().unwrap();
+        let cache = AsyncCache::builder()
+            .dir(cache_dir.path().to_owned())
+            .progress_bar(None)
+            .freshness_lifetime(300)
+            .build()
+            .unwrap();
+
+        // Mock the resource.
+        let fixture = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+        let resource = fixture.url.as_str();
+
+        // Get the cached path.
+        let path = runtime.block_on(cache.cached_path(resource)).unwrap();
+        assert_eq!(fixture.head.hits(), 1);
+        assert_eq!(fixture.get.hits(), 1);
+        assert!(path.is_file());
+        let meta = Meta::from_cache(&path).unwrap();
+        assert_eq!(meta.etag(), Some("fake-etag"));
+        assert_eq!(meta.size(), Some(14));
+        let contents = fs::read_to_string(&path).unwrap().replace("\r\n", "\n");
+        assert_eq!(&contents, "Hello, World!\n");
+
+        // The cached version is still fresh.
+        let same_path = runtime.block_on(cache.cached_path(resource)).unwrap();
+        assert_eq!(same_path, path);
+        assert_eq!(fixture.head.hits(), 1);
+        assert_eq!(fixture.get.hits(), 1);
+
+        // The blocking cache finds the same version.
+        let blocking_cache = Cache::builder()
+            .dir(cache_dir.path().to_owned())
+            .progress_bar(None)
+            .offline(true)
+            .build()
+            .unwrap();
+        assert_eq!(blocking_cache.cached_path(resource).unwrap(), path);
+    }
+
+    #[test]
+    fn test_remote_source() {
+        let runtime = runtime();
+        let source = Arc::new(MemorySource::default());
+        source.put("mem://hello.txt", "v1", b"Hello, World!");
+
+        let cache_dir = tempdir().unwrap();
+        let cache = AsyncCache::builder()
+            .dir(cache_dir.path().to_owned())
+            .progress_bar(None)
+            .remote_source("mem", source.clone())
+            .remote_source("https", source.clone())
+            .build()
+            .unwrap();
+        let path = runtime
+            .block_on(cache.cached_path("mem://hello.txt"))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Hello, World!");
+        assert_eq!(
+            runtime
+                .block_on(cache.cached_path("mem://hello.txt"))
+                .unwrap(),
+            path
+        );
+        assert_eq!(source.fetches.lock().unwrap().len(), 1);
+
+        // A source registered for `https` is used instead of the HTTP client.
+        source.put("https://example.invalid/hello.txt", "v1", b"Hello, HTTPS!");
+        let path = runtime
+            .block_on(cache.cached_path("https://example.invalid/hello.txt"))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Hello, HTTPS!");
+        assert_eq!(source.fetches.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_default_sources() {
+        let runtime = runtime();
+        let cache_dir = tempdir().unwrap();
+
+        // Async caches are usually built within a runtime.
+        let cache = runtime
+            .block_on(async {
+                AsyncCache::builder()
+                    .dir(cache_dir.path().to_owned())
+                    .progress_bar(None)
+                    .build()
+            })
+            .unwrap();
+
+        // The default sources are registered, so invalid resources are rejected by them
+        // before any requests are made.
+        for resource in &["s3://bucket", "gs://bucket", "hf://org/repo"] {
+            assert!(matches!(
+                runtime.block_on(cache.cached_path(resource)),
+                Err(Error::InvalidUrl(_))
+            ));
+        }
+        assert!(matches!(
+            runtime.block_on(cache.cached_path("other://data/hello.txt")),
+            Err(Error::UnsupportedScheme(scheme)) if scheme == "other"
+        ));
+    }
+
+    /// Build an async cache in `dir` that fetches resources with the given scheme through
+    /// `source`.
+    fn async_cache_with_source(
+        dir: PathBuf,
+        scheme: &str,
+        source: Arc<dyn RemoteSource>,
+    ) -> AsyncCache {
+        AsyncCache::builder()
+            .dir(dir)
+            .progress_bar(None)
+            .remote_source(scheme, source)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_s3_source() {
+        let server = MockServer::start();
+        let runtime = runtime();
+        let (head, get) = mock_s3_object(&server);
+
+        let cache_dir = tempdir().unwrap();
+        let s3 = crate::S3Source::new()
+            .endpoint(&server.url(""))
+            .region("us-west-2")
+            .credentials(
+                crate::S3Credentials::new("AKID", "secret").session_token("session-token"),
+            );
+        let cache = async_cache_with_source(cache_dir.path().to_owned(), "s3", Arc::new(s3));
+        let cached_path = |resource: &str| runtime.block_on(cache.cached_path(resource));
+        assert_cached_once(
+            cached_path,
+            "s3://bucket/models/model.bin",
+            "model weights",
+            "\"abc\"",
+            &head,
+            &get,
+        );
+        assert_missing(cached_path, "s3://bucket/missing.txt");
+    }
+
+    #[test]
+    fn test_hf_source() {
+        let hub = MockServer::start();
+        let runtime = runtime();
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let (head, get) = mock_hf_file(&hub, commit);
+
+        let cache_dir = tempdir().unwrap();
+        let hf = crate::HfSource::new()
+            .unwrap()
+            .endpoint(&hub.url(""))
+            .token("hf-token");
+        let cache = async_cache_with_source(cache_dir.path().to_owned(), "hf", Arc::new(hf));
+        let cached_path = |resource: &str| runtime.block_on(cache.cached_path(resource));
+        assert_cached_once(
+            cached_path,
+            "hf://org/repo/config.json",
+            "{\"hidden_size\": 8}",
+            commit,
+            &head,
+            &get,
+        );
+        assert_missing(cached_path, "hf://org/repo/missing.txt");
+    }
+
+    #[test]
+    fn test_gcs_source() {
+        let server = MockServer::start();
+        let runtime = runtime();
+        let (metadata, media) = mock_gcs_object(&server);
+
+        let cache_dir = tempdir().unwrap();
+        let gcs = crate::GcsSource::new()
+            .endpoint(&server.url(""))
+            .access_token("ya29.token");
+        let cache = async_cache_with_source(cache_dir.path().to_owned(), "gs", Arc::new(gcs));
+        let cached_path = |resource: &str| runtime.block_on(cache.cached_path(resource));
+        assert_cached_once(
+            cached_path,
+            "gs://bucket/models/model.bin",
+            "model weights",
+            "1700000000000001",
+            &metadata,
+            &media,
+        );
+        assert_missing(cached_path, "gs://bucket/missing.txt");
+    }
+
+    #[test]
+    fn test_copy_local_files() {
+        let runtime = runtime();
+        let cache_dir = tempdir().unwrap();
+        let cache = AsyncCache::builder()
+            .dir(cache_dir.path().to_owned())
+            .progress_bar(None)
+            .copy_local_files(true)
+            .build()
+            .unwrap();
+
+        let data_dir = tempdir().unwrap();
+        let file_path = data_dir.path().join("data.txt");
+        fs::write(&file_path, "Hello, World!").unwrap();
+        let url = reqwest::Url::from_file_path(&file_path).unwrap();
+        let path = runtime.block_on(cache.cached_path(url.as_str())).unwrap();
+        assert!(path.starts_with(cache_dir.path()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Hello, World!");
+    }
+
+    #[test]
+    fn test_resume_download_with_checksum() {
+        let server = MockServer::start();
+        let runtime = runtime();
+
+        // Setup cache.
+        let cache_dir = tempdir().unwrap();
+        let cache = AsyncCache::builder()
+            .dir(cache_dir.path().to_owned())
+            .progress_bar(None)
+            .build()
+            .unwrap();
+
+        // Mock a server that supports range requests.
+        let head = server.mock(|when, then| {
+            when.method(HEAD).path("/hello.txt");
+            then.status(200).header(ETAG_KEY, "fake-etag");
+        });
+        let get_range = server.mock(|when, then| {
+            when.method(GET)
+                .path("/hello.txt")
+                .header("Range", "bytes=7-")
+                .header("If-Range", "fake-etag");
+            then.status(206)
+                .header(ETAG_KEY, "fake-etag")
+                .header("Content-Range", "bytes 7-13/14")
+                .body("World!\n");
+        });
+        let resource = server.url("/hello.txt");
+
+        // Simulate a previous download that got interrupted.
+        let blocking_cache = Cache::builder()
+            .dir(cache_dir.path().to_owned())
+            .build()
+            .unwrap();
+        let path =
+            blocking_cache.resource_to_filepath(&resource, &Some("fake-etag".into()), None, None);
+        let partial_path = Cache::partial_path(&path);
+        fs::write(&partial_path, "Hello, ").unwrap();
+
+        // A mismatched checksum fails and throws away the partial file.
+        let wrong = "sha256:0000000000000000000000000000000000000000000000000000000000000000";
+        let err = runtime
+            .block_on(
+                cache.cached_path_with_options(&resource, &Options::default().checksum(wrong)),
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::ChecksumMismatch { .. }));
+        assert!(!partial_path.exists());
+        assert!(!path.exists());
+
+        fs::write(&partial_path, "Hello, ").unwrap();
+        let checksum = format!("sha256:{:x}", Sha256::digest(b"Hello, World!\n"));
+        let cached_path = runtime
+            .block_on(
+                cache.cached_path_with_options(&resource, &Options::default().checksum(&checksum)),
+            )
+            .unwrap();
+        assert_eq!(cached_path, path);
+        assert_eq!(head.hits(), 2);
+        assert_eq!(get_range.hits(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Hello, World!\n");
+        assert!(!partial_path.exists());
+        assert_eq!(
+            Meta::from_cache(&path).unwrap().checksum(),
+            Some(checksum.as_str())
+        );
+    }
+
+    #[test]
+    fn test_resume_download_falls_back_to_full_download() {
+        let server = MockServer::start();
+        let runtime = runtime();
+
+        // Setup cache.
+        let cache_dir = tempdir().unwrap();
+        let cache = AsyncCache::builder()
+            .dir(cache_dir.path().to_owned())
+            .progress_bar(None)
+            .build()
+            .unwrap();
+
+        // Mock the resource. The server ignores range requests.
+        let fixture = Fixture::load(&server, "test_fixtures/hello.txt", "fake-etag");
+
+        // Simulate a previous download that got interrupted, with a partial file
+        // that's longer than the resource itself.
+        let blocking_cache = Cache::builder()
+            .dir(cache_dir.path().to_owned())
+            .build()
+            .unwrap();
+        let path = blocking_cache.resource_to_filepath(
+            &fixture.url,
+            &Some("fake-etag".into()),
+            None,
+            None,
+        );
+        let partial_path = Cache::partial_path(&path);
+        fs::write(&partial_path, "Goodbye, cruel world!\n").unwrap();
+
+        let cached_path = runtime.block_on(cache.cached_path(&fixture.url)).unwrap();
+        assert_eq!(cached_path, path);
+        assert_eq!(fixture.get.hits(), 1);
+        let contents = fs::read_to_string(&path).unwrap().replace("\r\n", "\n");
+        assert_eq!(&contents, "Hello, World!\n");
+        assert!(!partial_path.exists());
+    }
+
+    #[test]
+    fn test_conditional_get() {
+        let server = MockServer::start();
+        let runtime = runtime();
+
+        // Setup cache.
+        let cache_dir = tempdir().unwrap();
+        let cache = AsyncCache::builder()
+            .dir(cache_dir.path().to_owned())
+            .progress_bar(None)
+            .conditional_get(true)
+            .build()
+            .unwrap();
+
+        // Mock the resource. Conditional requests for the current version get a 304.
+        let not_modified = server.mock(|when, then| {
+            when.method(GET)
+                .path("/hello.txt")
+                .header("If-None-Match", "fake-etag");
+            then.status(304).header(ETAG_KEY, "fake-etag");
+        });
+        let get = server.mock(|when, then| {
+            when.method(GET).path("/hello.txt");
+            then.status(200)
+                .header(ETAG_KEY, "fake-etag")
+                .body("Hello, World!\n");
+        });
+        let resource = server.url("/hello.txt");
+
+        let path = runtime.block_on(cache.cached_path(&resource)).unwrap();
+        assert_eq!(get.hits(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Hello, World!\n");
+
+        let same_path = runtime.block_on(cache.cached_path(&resource)).unwrap();
+        assert_eq!(same_path, path);
+        assert_eq!(get.hits(), 1);
+        assert_eq!(not_modified.hits(), 1);
+    }
+
+    #[test]
+    fn test_extract_remote_tar_gz() {
+        let server = MockServer::start();
+        let runtime = runtime();
+
+        // Setup cache.
+        let cache_dir = tempdir().unwrap();
+        let cache = AsyncCache::builder()
+            .dir(cache_dir.path().to_owned())
+            .progress_bar(None)
+            .build()
+            .unwrap();
+
+        // Mock the resource.
+        let contents = fs::read("test_fixtures/utf-8_sample/archives/utf-8.tar.gz").unwrap();
+        server.mock(|when, then| {
+            when.method(HEAD).path("/utf-8.tar.gz");
+            then.status(200).header(ETAG_KEY, "fake-etag");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/utf-8.tar.gz");
+            then.status(200)
+                .header(ETAG_KEY, "fake-etag")
+                .body(&contents);
+        });
+        let resource = server.url("/utf-8.tar.gz");
+
+        let path = runtime
+            .block_on(cache.cached_path_with_options(&resource, &Options::default().extract()))
+            .unwrap();
+        assert!(path.is_dir());
+        assert!(path.to_str().unwrap().ends_with("-extracted"));
+        assert!(path.join("dummy.txt").is_file());
+
+        // Members of the archive can be addressed directly.
+        let member = runtime
+            .block_on(cache.cached_path(&format!("{}!/dummy.txt", resource)))
+            .unwrap();
+        assert_eq!(member, path.join("dummy.txt"));
+    }
+
+    #[test]
+    fn test_futures_are_send() {
+        fn assert_send<T: Send>(_: T) {}
+
+        let cache_dir = tempdir().unwrap();
+        let cache = AsyncCache::builder()
+            .dir(cache_dir.path().to_owned())
+            .build()
+            .unwrap();
+        assert_send(cache.cached_path("README.md"));
+        assert_send(cache.cached_path_with_options("README.md", &Options::default()));
+    }
 }
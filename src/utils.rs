@@ -1,4 +1,7 @@
 use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
 pub(crate) fn hash_str(s: &str) -> String {
@@ -12,3 +15,79 @@ pub(crate) fn now() -> f64 {
         .unwrap()
         .as_secs_f64()
 }
+
+/// Percent-encode everything but unreserved characters (RFC 3986), leaving slashes
+/// alone unless `encode_slash` is set.
+pub(crate) fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decode percent-encoded bytes, leaving invalid escapes as they are. Invalid UTF-8 is
+/// replaced with the replacement character.
+pub(crate) fn uri_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Get the value of an environment variable, treating empty values as unset.
+pub(crate) fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Get the home directory of the current user.
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// Get the total size in bytes of a file or directory, recursively.
+///
+/// Returns 0 if the path doesn't exist.
+pub(crate) fn disk_usage(path: &Path) -> u64 {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if metadata.is_dir() {
+        fs::read_dir(path)
+            .map(|read_dir| {
+                read_dir
+                    .filter_map(Result::ok)
+                    .map(|entry| disk_usage(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or(0)
+    } else {
+        metadata.len()
+    }
+}
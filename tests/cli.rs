@@ -1,9 +1,8 @@
-
 use assert_cmd::prelude::*; // Add methods on commands
 use predicates::prelude::*; // Used for writing assertions
 use std::fs;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command; // Run programs
 use tempfile::tempdir;
 
@@ -108,4 +107,263 @@ fn test_extract_local_file() -> Result<(), Box<dyn std::error::Error>> {
     assert!(path.join("folder").join("utf-8_sample.txt").is_file());
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_manage_cache() -> Result<(), Box<dyn std::error::Error>> {
+    use httpmock::Method::{GET, HEAD};
+    use httpmock::MockServer;
+
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(HEAD).path("/hello.txt");
+        then.status(200).header("ETag", "fake-etag");
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/hello.txt");
+        then.status(200)
+            .header("ETag", "fake-etag")
+            .body("Hello, World!");
+    });
+    let resource = server.url("/hello.txt");
+    let cache_dir = tempdir().unwrap();
+    let cached_path = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("cached-path").unwrap();
+        cmd.arg("--dir").arg(cache_dir.path()).args(args);
+        cmd
+    };
+
+    // 'get' works like the legacy form.
+    let output = cached_path(&["get", "-q", &resource]).output()?;
+    assert!(output.status.success());
+    let path = PathBuf::from(String::from_utf8(output.stdout)?.trim_end());
+    assert!(path.is_file());
+    cached_path(&["-q", &resource])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(path.to_str().unwrap()));
+
+    cached_path(&["ls"]).assert().success().stdout(
+        predicate::str::contains("RESOURCE")
+            .and(predicate::str::contains(resource.as_str()))
+            .and(predicate::str::contains("fake-etag")),
+    );
+    let output = cached_path(&["ls", "--json"]).output()?;
+    let entries: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(entries[0]["resource"], resource.as_str());
+    assert_eq!(entries[0]["etag"], "fake-etag");
+    assert_eq!(entries[0]["size"], 13);
+
+    cached_path(&["info", &resource])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fake-etag"));
+    cached_path(&["info", "https://example.com/missing.txt"])
+        .assert()
+        .failure();
+
+    // 'verify' catches corrupted files.
+    cached_path(&["verify"]).assert().success();
+    fs::write(&path, "Hello!")?;
+    cached_path(&["verify"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("FAILED"));
+
+    cached_path(&["rm", &resource]).assert().success();
+    assert!(!path.exists());
+    let output = cached_path(&["ls", "--json"]).output()?;
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&output.stdout)?,
+        serde_json::json!([])
+    );
+
+    // 'clean' removes old resources or evicts resources until the cache is small enough.
+    cached_path(&["get", "-q", &resource]).assert().success();
+    cached_path(&["clean", "--older-than", "1h"])
+        .assert()
+        .success();
+    assert!(path.exists());
+    cached_path(&["clean", "--max-size", "1K"])
+        .assert()
+        .success();
+    assert!(path.exists());
+    cached_path(&["clean", "--max-size", "0"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(path.to_str().unwrap()));
+    assert!(!path.exists());
+    cached_path(&["clean"]).assert().failure();
+    cached_path(&["clean", "--older-than", "99999999999999999w"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid duration"));
+    cached_path(&["clean", "--max-size", "99999999999999999T"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid size"));
+
+    Ok(())
+}
+
+#[test]
+fn test_prefetch() -> Result<(), Box<dyn std::error::Error>> {
+    use httpmock::Method::{GET, HEAD};
+    use httpmock::MockServer;
+
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(HEAD).path("/hello.txt");
+        then.status(200).header("ETag", "fake-etag");
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/hello.txt");
+        then.status(200)
+            .header("ETag", "fake-etag")
+            .body("Hello, World!");
+    });
+    server.mock(|when, then| {
+        when.path("/missing.txt");
+        then.status(404);
+    });
+    let cache_dir = tempdir().unwrap();
+    let manifest_dir = tempdir().unwrap();
+    let prefetch = |manifest: &str, contents: &str| {
+        let manifest_path = manifest_dir.path().join(manifest);
+        fs::write(&manifest_path, contents).unwrap();
+        let mut cmd = Command::cargo_bin("cached-path").unwrap();
+        cmd.arg("--dir")
+            .arg(cache_dir.path())
+            .args(["prefetch", "-q", "--max-retries", "0", "-j", "2"])
+            .arg(manifest_path);
+        cmd
+    };
+
+    let json_manifest = format!(
+        r#"[
+            "{}",
+            {{ "resource": "{}", "subdir": "target" }},
+            {{ "resource": "test_fixtures/utf-8_sample/archives/utf-8.zip", "extract": true }}
+        ]"#,
+        server.url("/hello.txt"),
+        server.url("/hello.txt"),
+    );
+    let output = prefetch("manifest.json", &json_manifest).output()?;
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(report["succeeded"], 3);
+    assert_eq!(report["failed"], 0);
+    let results = report["results"].as_array().unwrap();
+    assert_eq!(results[0]["resource"], server.url("/hello.txt").as_str());
+    assert!(PathBuf::from(results[0]["path"].as_str().unwrap()).is_file());
+    assert!(results[1]["path"].as_str().unwrap().contains("target"));
+    assert!(PathBuf::from(results[2]["path"].as_str().unwrap())
+        .join("dummy.txt")
+        .is_file());
+
+    let toml_manifest = format!(
+        "[[resources]]\nresource = \"{}\"\nsubdir = \"toml\"\n",
+        server.url("/hello.txt"),
+    );
+    let output = prefetch("manifest.toml", &toml_manifest).output()?;
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert!(report["results"][0]["path"]
+        .as_str()
+        .unwrap()
+        .contains("toml"));
+
+    // Failures are reported without stopping the other resources from being fetched.
+    let txt_manifest = format!(
+        "# Resources to prefetch.\n{}\n\n{}\n",
+        server.url("/missing.txt"),
+        server.url("/hello.txt"),
+    );
+    let output = prefetch("manifest.txt", &txt_manifest).output()?;
+    assert!(!output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(report["succeeded"], 1);
+    assert_eq!(report["failed"], 1);
+    assert!(report["results"][0]["error"]
+        .as_str()
+        .unwrap()
+        .contains("404"));
+    assert!(report["results"][1]["path"].is_string());
+
+    Ok(())
+}
+
+#[test]
+fn test_export_import() -> Result<(), Box<dyn std::error::Error>> {
+    use httpmock::Method::{GET, HEAD};
+    use httpmock::MockServer;
+
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(HEAD).path("/hello.txt");
+        then.status(200).header("ETag", "fake-etag");
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/hello.txt");
+        then.status(200)
+            .header("ETag", "fake-etag")
+            .body("Hello, World!");
+    });
+    let resource = server.url("/hello.txt");
+    let cache_dir = tempdir().unwrap();
+    let other_cache_dir = tempdir().unwrap();
+    let bundle = cache_dir.path().join("bundle.tar");
+    let cached_path = |dir: &Path| {
+        let mut cmd = Command::cargo_bin("cached-path").unwrap();
+        cmd.arg("--dir").arg(dir);
+        cmd
+    };
+
+    cached_path(cache_dir.path())
+        .args(["get", "-q", &resource])
+        .assert()
+        .success();
+    cached_path(cache_dir.path())
+        .args(["export", "-o"])
+        .arg(&bundle)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(resource.as_str()));
+    cached_path(cache_dir.path())
+        .args(["export", "https://example.com/missing.txt"])
+        .assert()
+        .failure();
+
+    let output = cached_path(other_cache_dir.path())
+        .arg("import")
+        .arg(&bundle)
+        .output()?;
+    assert!(output.status.success());
+    let path = PathBuf::from(String::from_utf8(output.stdout)?.trim_end());
+    assert!(path.starts_with(other_cache_dir.path()));
+    assert_eq!(fs::read_to_string(&path)?, "Hello, World!");
+
+    // The imported resource can be used offline.
+    cached_path(other_cache_dir.path())
+        .args(["get", "--offline", &resource])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(path.to_str().unwrap()));
+
+    // Bundles can also be piped through stdout and stdin.
+    let output = cached_path(cache_dir.path()).arg("export").output()?;
+    assert!(output.status.success());
+    let third_cache_dir = tempdir().unwrap();
+    assert_cmd::Command::cargo_bin("cached-path")?
+        .arg("--dir")
+        .arg(third_cache_dir.path())
+        .arg("import")
+        .write_stdin(output.stdout)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            third_cache_dir.path().to_str().unwrap(),
+        ));
+
+    Ok(())
+}